@@ -0,0 +1,9 @@
+/**
+ * Fetches the config's urls and extracts them, resolving to the document
+ * the CLI writes: the page object of a single `url` or the list of pages,
+ * shaped by `output`. Nothing is written to files, databases or streams.
+ * The config is the YAML source or the same shape as an object
+ */
+export function scrape(config: string | Record<string, unknown>): Promise<unknown>
+/** Extracts the `data` fields from HTML the caller already has */
+export function scrapeHtml(html: string, data: Record<string, unknown>): Record<string, unknown>
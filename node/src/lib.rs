@@ -0,0 +1,81 @@
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::Utc;
+use karkinos::antibot::Aborted;
+use karkinos::pipeline::{Pipeline, ScrapeOptions};
+use karkinos::types::{validate_data_selectors, DataConfig, Page, ScrapeRoot};
+use karkinos::{parse_config_value, populate_values};
+use napi::bindgen_prelude::AsyncTask;
+use napi::{Env, Error, JsUnknown, Result, Task};
+use napi_derive::napi;
+use serde_json::Value;
+
+/// Fetches the config's urls and extracts them, resolving to the document
+/// the CLI writes: the page object of a single `url` or the list of pages,
+/// shaped by `output`. Nothing is written to files, databases or streams.
+/// The config is the YAML source or the same shape as an object
+#[napi(ts_return_type = "Promise<unknown>")]
+pub fn scrape(config: Value) -> Result<AsyncTask<Scrape>> {
+    let config = parse_config(config).map_err(to_napi)?;
+
+    Ok(AsyncTask::new(Scrape { config }))
+}
+
+/// Extracts the `data` fields from HTML the caller already has
+#[napi(js_name = "scrapeHtml", ts_return_type = "Record<string, unknown>")]
+pub fn scrape_html(html: String, data: Value) -> Result<Value> {
+    let data = serde_json::from_value::<DataConfig>(data)
+        .map_err(|error| Error::from_reason(format!("invalid data: {}", error)))?;
+    validate_data_selectors(&data)
+        .map_err(|error| Error::from_reason(format!("invalid data: {}", error)))?;
+    let page = Page {
+        html,
+        ..Default::default()
+    };
+
+    serde_json::to_value(populate_values(&page, &data)).map_err(|error| to_napi(error.into()))
+}
+
+/// A scrape on the libuv thread pool, fetches block
+pub struct Scrape {
+    config: ScrapeRoot,
+}
+
+impl Task for Scrape {
+    type Output = Value;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> Result<Value> {
+        scrape_pages(&self.config).map_err(to_napi)
+    }
+
+    fn resolve(&mut self, env: Env, output: Value) -> Result<JsUnknown> {
+        env.to_js_value(&output)
+    }
+}
+
+fn parse_config(config: Value) -> AnyhowResult<ScrapeRoot> {
+    match config {
+        Value::String(source) => karkinos::parse_config(&source),
+        config => parse_config_value(serde_yaml::to_value(config).context("invalid config")?),
+    }
+}
+
+fn scrape_pages(config: &ScrapeRoot) -> AnyhowResult<Value> {
+    let started_at = Utc::now();
+    let pipeline = Pipeline::new(config)?;
+    let fetcher = pipeline.fetcher()?;
+
+    let targets = config.config.targets();
+    let mut scraped = pipeline.scrape(&fetcher, &targets, &ScrapeOptions::default(), &mut ())?;
+    if !scraped.errors.is_empty() {
+        let errors = &mut scraped.errors;
+        let index = errors.iter().position(|error| error.is::<Aborted>());
+        return Err(errors.remove(index.unwrap_or(0)));
+    }
+
+    Ok(pipeline.output(&scraped, started_at)?.document)
+}
+
+fn to_napi(error: anyhow::Error) -> Error {
+    Error::from_reason(format!("{:#}", error))
+}
@@ -0,0 +1,93 @@
+use serde_json::{json, Map, Value};
+
+use crate::alerts::{field_text, number};
+use crate::types::{AggregateConfig, AggregateField, AggregateOp, ReturnedData};
+
+/// Computes every configured statistic, numeric statistics skip values that
+/// are not numbers and are `null` without any
+pub fn aggregate(config: &AggregateConfig, records: &[&ReturnedData]) -> Map<String, Value> {
+    config
+        .fields
+        .iter()
+        .map(|(name, field)| (name.clone(), statistic(field, records)))
+        .collect()
+}
+
+fn statistic(config: &AggregateField, records: &[&ReturnedData]) -> Value {
+    let Some(field) = &config.field else {
+        return json!(records.len());
+    };
+
+    let values = records
+        .iter()
+        .filter_map(|record| record.get(field).and_then(field_text))
+        .filter(|value| !value.is_empty());
+
+    match config.op {
+        AggregateOp::Count => json!(values.count()),
+        AggregateOp::Distinct => {
+            let mut distinct: Vec<String> = vec![];
+            for value in values {
+                if !distinct.contains(&value) {
+                    distinct.push(value);
+                }
+            }
+            json!(distinct)
+        }
+        op => {
+            let numbers = values
+                .filter_map(|value| number(&value))
+                .collect::<Vec<_>>();
+            if numbers.is_empty() {
+                return Value::Null;
+            }
+
+            let sum = numbers.iter().sum::<f64>();
+            json!(match op {
+                AggregateOp::Min => numbers.iter().copied().fold(f64::INFINITY, f64::min),
+                AggregateOp::Max => numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                AggregateOp::Avg => sum / numbers.len() as f64,
+                _ => sum,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::aggregate;
+    use crate::types::ReturnedDataItem::StringItem;
+    use crate::types::{AggregateConfig, ReturnedData};
+    use serde_json::json;
+
+    #[test]
+    fn aggregate_test() {
+        let record = |company: &str, price: &str| {
+            ReturnedData::from([
+                ("company".to_string(), StringItem(company.to_string())),
+                ("price".to_string(), StringItem(price.to_string())),
+            ])
+        };
+        let records = [record("A", "$10"), record("B", "20"), record("A", "n/a")];
+        let config: AggregateConfig = serde_yaml::from_str(
+            "fields:
+               total: { op: count }
+               priced: { op: count, field: price }
+               cheapest: { op: min, field: price }
+               average: { op: avg, field: price }
+               companies: { op: distinct, field: company }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            json!(aggregate(&config, &records.iter().collect::<Vec<_>>())),
+            json!({
+                "total": 3,
+                "priced": 3,
+                "cheapest": 10.0,
+                "average": 15.0,
+                "companies": ["A", "B"],
+            })
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{bail, Result as AnyhowResult};
+use log::warn;
+
+use crate::output::{records, render};
+use crate::types::{AlertsConfig, ReturnedData, ReturnedDataItem, ReturnedPage};
+
+/// Longest operators first, so `<=` is not read as `<`
+const OPERATORS: [(&str, Operator); 7] = [
+    ("==", Operator::Eq),
+    ("!=", Operator::Ne),
+    ("<=", Operator::Le),
+    (">=", Operator::Ge),
+    ("<", Operator::Lt),
+    (">", Operator::Gt),
+    (" contains ", Operator::Contains),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+/// `field op value` condition of an alert rule
+#[derive(Debug, PartialEq)]
+pub struct Condition {
+    field: String,
+    operator: Operator,
+    value: String,
+}
+
+/// A record that matched a rule
+pub struct Alert<'a> {
+    pub record: &'a ReturnedData,
+    pub message: String,
+}
+
+impl Condition {
+    pub fn parse(when: &str) -> AnyhowResult<Self> {
+        let found = when.char_indices().find_map(|(index, _)| {
+            OPERATORS
+                .iter()
+                .find(|(symbol, _)| when[index..].starts_with(symbol))
+                .map(|(symbol, operator)| (index, symbol.len(), *operator))
+        });
+        let Some((index, length, operator)) = found else {
+            bail!("no operator in condition `{}`", when);
+        };
+
+        let field = when[..index].trim();
+        let value = when[index + length..].trim();
+        if field.is_empty() || value.is_empty() {
+            bail!("condition `{}` needs a field and a value", when);
+        }
+
+        Ok(Condition {
+            field: field.to_string(),
+            operator,
+            value: unquote(value).to_string(),
+        })
+    }
+
+    pub fn matches(&self, record: &ReturnedData) -> bool {
+        let Some(actual) = record.get(&self.field).and_then(field_text) else {
+            return false;
+        };
+
+        match (self.operator, number(&actual).zip(number(&self.value))) {
+            (Operator::Contains, _) => actual.contains(&self.value),
+            (Operator::Eq, None) => self.text_equals(&actual),
+            (Operator::Ne, None) => !self.text_equals(&actual),
+            // text is never smaller or greater than anything
+            (_, None) => false,
+            (Operator::Eq, Some((actual, expected))) => actual == expected,
+            (Operator::Ne, Some((actual, expected))) => actual != expected,
+            (Operator::Lt, Some((actual, expected))) => actual < expected,
+            (Operator::Le, Some((actual, expected))) => actual <= expected,
+            (Operator::Gt, Some((actual, expected))) => actual > expected,
+            (Operator::Ge, Some((actual, expected))) => actual >= expected,
+        }
+    }
+
+    /// `true` and `false` match regardless of case
+    fn text_equals(&self, actual: &str) -> bool {
+        if is_bool(&self.value) {
+            actual.eq_ignore_ascii_case(&self.value)
+        } else {
+            actual == self.value
+        }
+    }
+}
+
+/// Records matching any rule, one alert per record and matched rule
+pub fn check<'a>(config: &AlertsConfig, pages: &'a [ReturnedPage]) -> AnyhowResult<Vec<Alert<'a>>> {
+    let conditions = config
+        .rules
+        .iter()
+        .map(|rule| Condition::parse(&rule.when))
+        .collect::<AnyhowResult<Vec<_>>>()?;
+
+    let mut alerts = vec![];
+    for record in records(config.records.as_ref(), pages)? {
+        for (rule, condition) in config.rules.iter().zip(&conditions) {
+            if !condition.matches(record) {
+                continue;
+            }
+
+            let message = match &rule.message {
+                Some(template) => render(template, record),
+                None => format!(
+                    "{}: {}",
+                    rule.when,
+                    serde_json::to_string(record).unwrap_or_default()
+                ),
+            };
+            warn!("alert: {}", message);
+            alerts.push(Alert { record, message });
+        }
+    }
+
+    Ok(alerts)
+}
+
+/// Lists are compared by their number of items
+pub fn field_text(value: &ReturnedDataItem) -> Option<String> {
+    match value {
+        ReturnedDataItem::StringItem(value) => Some(value.trim().to_string()),
+        ReturnedDataItem::DataItems(items) => Some(items.len().to_string()),
+        ReturnedDataItem::JsonItem(serde_json::Value::Null) => None,
+        ReturnedDataItem::JsonItem(serde_json::Value::String(value)) => Some(value.clone()),
+        ReturnedDataItem::JsonItem(value) => Some(value.to_string()),
+    }
+}
+
+/// Reads prices like `$1,299.00` as numbers
+pub fn number(text: &str) -> Option<f64> {
+    let digits = text
+        .chars()
+        .filter(|char| char.is_ascii_digit() || *char == '.' || *char == '-')
+        .collect::<String>();
+    let has_letters = text.chars().any(|char| char.is_alphabetic());
+
+    if has_letters || digits.is_empty() {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn is_bool(value: &str) -> bool {
+    value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false")
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alerts::Condition;
+    use crate::types::ReturnedData;
+    use crate::types::ReturnedDataItem::StringItem;
+
+    #[test]
+    fn condition_test() {
+        let record = ReturnedData::from([
+            ("price".to_string(), StringItem("$1,299.00".to_string())),
+            ("inStock".to_string(), StringItem("True".to_string())),
+            ("title".to_string(), StringItem("Blue lamp".to_string())),
+        ]);
+        let matches = |when: &str| Condition::parse(when).unwrap().matches(&record);
+
+        assert!(matches("price < 1300"));
+        assert!(matches("price >= 1299"));
+        assert!(!matches("price > 1299"));
+        assert!(matches("inStock == true"));
+        assert!(matches("title contains 'lamp'"));
+        assert!(matches("title != \"Red lamp\""));
+        assert!(!matches("title < 5"));
+        assert!(!matches("missing == 1"));
+        assert!(Condition::parse("price 500").is_err());
+    }
+}
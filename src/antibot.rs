@@ -0,0 +1,100 @@
+use std::fmt;
+
+use crate::types::BlockDetector;
+
+/// Text only the block pages of each detector contain
+const MARKERS: [(BlockDetector, &[&str]); 5] = [
+    (
+        BlockDetector::Cloudflare,
+        &[
+            "/cdn-cgi/challenge-platform/",
+            "cf-browser-verification",
+            "cf_chl_opt",
+            "Attention Required! | Cloudflare",
+        ],
+    ),
+    (
+        BlockDetector::Perimeterx,
+        &["_pxCaptcha", "px-captcha", "client.perimeterx.net"],
+    ),
+    (
+        BlockDetector::Datadome,
+        &["captcha-delivery.com", "datadome.co/captcha"],
+    ),
+    (
+        BlockDetector::Recaptcha,
+        &["class=\"g-recaptcha\"", "google.com/recaptcha/api"],
+    ),
+    (
+        BlockDetector::Hcaptcha,
+        &["class=\"h-captcha\"", "hcaptcha.com/1/api.js"],
+    ),
+];
+
+/// The page is a block page, fetching it again may help
+#[derive(Debug)]
+pub struct Blocked {
+    pub url: String,
+    pub detector: BlockDetector,
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is a {:?} block page", self.url, self.detector)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// The run stops on the first block page
+#[derive(Debug)]
+pub struct Aborted {
+    pub blocked: Blocked,
+    pub exit_code: i32,
+}
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, aborting the run", self.blocked)
+    }
+}
+
+impl std::error::Error for Aborted {}
+
+/// The first of `detectors` whose block page this is
+pub fn detect(html: &str, detectors: &[BlockDetector]) -> Option<BlockDetector> {
+    MARKERS
+        .iter()
+        .filter(|(detector, _)| detectors.contains(detector))
+        .find(|(_, markers)| markers.iter().any(|marker| html.contains(marker)))
+        .map(|(detector, _)| *detector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_test() {
+        let all = [
+            BlockDetector::Cloudflare,
+            BlockDetector::Perimeterx,
+            BlockDetector::Datadome,
+            BlockDetector::Recaptcha,
+            BlockDetector::Hcaptcha,
+        ];
+        let challenge = r#"<title>Just a moment...</title>
+            <script src="/cdn-cgi/challenge-platform/h/g/orchestrate/jsch/v1"></script>"#;
+
+        assert_eq!(detect(challenge, &all), Some(BlockDetector::Cloudflare));
+        assert_eq!(
+            detect(r#"<div class="g-recaptcha"></div>"#, &all),
+            Some(BlockDetector::Recaptcha)
+        );
+        assert_eq!(
+            detect(r#"<div class="g-recaptcha"></div>"#, &all[..3]),
+            None
+        );
+        assert_eq!(detect("<h1>Lamp</h1>", &all), None);
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::{Context, Result as AnyhowResult};
+use scraper::ElementRef;
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::fetch::Fetcher;
+use crate::preprocess::preprocess;
+use crate::report::UrlReport;
+use crate::types::{DataConfig, Page};
+use crate::warc::read_archive;
+use crate::{extract, load_config, Document};
+
+/// Parsing time is reported under this name next to the fields
+const PARSE: &str = "(parse)";
+
+/// Extracts the config's pages `iterations` times and prints the time spent
+/// per field, slowest first
+pub fn bench(
+    input: &Path,
+    iterations: u32,
+    html: Option<&Path>,
+    from_warc: Option<&Path>,
+) -> AnyhowResult<()> {
+    let config = load_config(input)?;
+    let pages = match (html, from_warc) {
+        (Some(html), _) => vec![read_to_string(html)
+            .with_context(|| format!("could not read file `{}`", html.display()))?],
+        (None, Some(from_warc)) => {
+            let mut archive = read_archive(from_warc)?;
+            config
+                .config
+                .targets()
+                .iter()
+                .map(|target| {
+                    archive
+                        .remove(&target.url)
+                        .map(|page| page.html)
+                        .with_context(|| format!("`{}` is not in the warc archive", target.url))
+                })
+                .collect::<AnyhowResult<_>>()?
+        }
+        (None, None) => {
+            // fetched once, every iteration reuses the same html
+            let fetcher = Fetcher::new(&config.config, config.concurrency.per_host)?;
+            config
+                .config
+                .targets()
+                .iter()
+                .map(|target| {
+                    let page = fetcher.fetch(target, &mut UrlReport::new(&target.url))?;
+                    Ok(page.html)
+                })
+                .collect::<AnyhowResult<_>>()?
+        }
+    };
+
+    let pages = match &config.config.preprocess {
+        Some(config) => pages.iter().map(|html| preprocess(config, html)).collect(),
+        None => pages,
+    };
+
+    let pages = pages
+        .into_iter()
+        .map(|html| Page {
+            html,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    let mut timings = Timings::new();
+    for _ in 0..iterations {
+        for page in &pages {
+            let parse_started = Instant::now();
+            let document = Document::parse(page);
+            *timings.entry(PARSE.to_string()).or_default() += parse_started.elapsed();
+
+            let root = document.html.root_element();
+            time_fields(root, &config.data, "", false, &document, &mut timings);
+        }
+    }
+
+    let mut timings = timings.into_iter().collect::<Vec<_>>();
+    timings.sort_by(|(_, first), (_, second)| second.cmp(first));
+
+    let runs = iterations * pages.len() as u32;
+    println!("{:<40} {:>12} {:>12}", "field", "mean", "total");
+    for (field, total) in timings {
+        let mean = total.checked_div(runs).unwrap_or_default();
+        println!("{:<40} {:>12.3?} {:>12.3?}", field, mean, total);
+    }
+
+    Ok(())
+}
+
+/// total time per field path, nested fields are included in their parent's time
+type Timings = BTreeMap<String, Duration>;
+
+fn time_fields(
+    element: ElementRef,
+    config: &DataConfig,
+    prefix: &str,
+    include_self: bool,
+    document: &Document,
+    timings: &mut Timings,
+) {
+    for (name, item) in config {
+        let path = format!("{}{}", prefix, name);
+        let field = DataConfig::from([(name.clone(), item.clone())]);
+
+        let started = Instant::now();
+        extract(element, &field, include_self, document);
+        *timings.entry(path.clone()).or_default() += started.elapsed();
+
+        if let Some(inner) = &item.data {
+            let selector = item.get_item_selector();
+            let inner_prefix = format!("{}.", path);
+
+            let matched = include_self
+                .then_some(element)
+                .filter(|element| selector.matches(element))
+                .into_iter()
+                .chain(element.select(selector));
+            for matched in matched {
+                time_fields(matched, inner, &inner_prefix, true, document, timings);
+            }
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use indexmap::IndexMap;
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+use crate::types::Page;
+use crate::types::{
+    BrowserAction, BrowserConfig, Capture, InfiniteScroll, PaginationConfig, ReturnedData,
+    ReturnedDataItem, Target,
+};
+
+/// binaries looked up on `PATH` when neither `executable` nor `$CHROME` is set
+const EXECUTABLES: [&str; 4] = [
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+];
+
+/// how often `waitFor` is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// time given to the page to load more content after each scroll
+const SCROLL_PAUSE: Duration = Duration::from_millis(500);
+
+const HIDE_WEBDRIVER: &str =
+    "Object.defineProperty(Navigator.prototype, 'webdriver', { get: () => undefined });";
+
+/// fills in what headless Chrome leaves empty and real Chrome doesn't
+const STEALTH: &str = r#"
+window.chrome = window.chrome || { runtime: {} };
+Object.defineProperty(Navigator.prototype, 'plugins', { get: () => [1, 2, 3, 4, 5] });
+Object.defineProperty(Navigator.prototype, 'languages', {
+  get: () => navigator.language ? [navigator.language, navigator.language.split('-')[0]] : ['en-US', 'en'],
+});
+const query = navigator.permissions && navigator.permissions.query.bind(navigator.permissions);
+if (query) {
+  navigator.permissions.query = (parameters) => parameters.name === 'notifications'
+    ? Promise.resolve({ state: Notification.permission })
+    : query(parameters);
+}
+"#;
+
+/// serializes the page with every open shadow root's children appended to
+/// its host, after the host's own children
+const PIERCE_SHADOW: &str = r#"(() => {
+  const flatten = (source, target) => {
+    for (let i = 0; i < source.children.length; i++) {
+      flatten(source.children[i], target.children[i]);
+    }
+    if (source.shadowRoot) {
+      for (const child of source.shadowRoot.childNodes) {
+        const copy = child.cloneNode(true);
+        target.appendChild(copy);
+        if (child.nodeType === Node.ELEMENT_NODE) {
+          flatten(child, copy);
+        }
+      }
+    }
+  };
+  const root = document.documentElement.cloneNode(true);
+  flatten(document.documentElement, root);
+  return root.outerHTML;
+})()"#;
+
+/// closing a tab gets its own deadline, a slow render must not leak tabs
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Headless Chrome driven over the DevTools protocol, one tab per page
+pub struct Browser {
+    process: Child,
+    profile: PathBuf,
+    socket: Socket,
+    next_id: u64,
+    /// events received while waiting for something else
+    events: VecDeque<Value>,
+}
+
+impl Browser {
+    pub fn launch(config: &BrowserConfig) -> AnyhowResult<Self> {
+        let executable = match &config.executable {
+            Some(executable) => executable.clone(),
+            None => find_executable()?,
+        };
+        let profile = env::temp_dir().join(format!("karkinos-chrome-{}", process::id()));
+
+        let mut args = vec![];
+        if let Some(viewport) = &config.viewport {
+            args.push(format!(
+                "--window-size={},{}",
+                viewport.width, viewport.height
+            ));
+        }
+        if let Some(locale) = &config.locale {
+            args.push(format!("--lang={}", locale));
+        }
+        if config.hide_webdriver || config.stealth {
+            args.push("--disable-blink-features=AutomationControlled".to_string());
+        }
+
+        let mut process = Command::new(&executable)
+            .args([
+                "--headless=new",
+                "--remote-debugging-port=0",
+                "--no-first-run",
+                "--no-default-browser-check",
+                "--disable-gpu",
+                "--disable-dev-shm-usage",
+            ])
+            .args(args)
+            .arg(format!("--user-data-dir={}", profile.display()))
+            .arg("about:blank")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("can't start browser `{}`", executable))?;
+
+        let socket = match connect(&mut process, &executable) {
+            Ok(socket) => socket,
+            Err(error) => {
+                let _ = process.kill();
+                return Err(error);
+            }
+        };
+
+        Ok(Browser {
+            process,
+            profile,
+            socket,
+            next_id: 0,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Loads `url` in a new tab and returns the DOM once the wait conditions are met
+    pub fn render(
+        &mut self,
+        target: &Target,
+        config: &BrowserConfig,
+        pagination: Option<&PaginationConfig>,
+    ) -> AnyhowResult<Page> {
+        let deadline = Instant::now() + Duration::from_millis(config.timeout);
+
+        let created = self.call(
+            None,
+            "Target.createTarget",
+            json!({ "url": "about:blank" }),
+            deadline,
+        )?;
+        let target_id = created["targetId"]
+            .as_str()
+            .context("browser did not open a tab")?
+            .to_string();
+
+        let result = self
+            .call(
+                None,
+                "Target.attachToTarget",
+                json!({ "targetId": target_id, "flatten": true }),
+                deadline,
+            )
+            .and_then(|attached| {
+                let session = attached["sessionId"]
+                    .as_str()
+                    .context("browser did not attach to the tab")?
+                    .to_string();
+
+                self.render_in(&session, target, config, pagination, deadline)
+            });
+
+        self.events.clear();
+        let closed = self.call(
+            None,
+            "Target.closeTarget",
+            json!({ "targetId": target_id }),
+            Instant::now() + CLOSE_TIMEOUT,
+        );
+        if let Err(error) = closed {
+            warn!("can't close browser tab: {}", error);
+        }
+
+        result
+    }
+
+    fn render_in(
+        &mut self,
+        session: &str,
+        target: &Target,
+        config: &BrowserConfig,
+        pagination: Option<&PaginationConfig>,
+        deadline: Instant,
+    ) -> AnyhowResult<Page> {
+        let url = &target.url;
+        self.call(Some(session), "Page.enable", json!({}), deadline)?;
+        self.call(Some(session), "Network.enable", json!({}), deadline)?;
+        self.emulate(session, config, deadline)?;
+
+        let navigation = self.call(
+            Some(session),
+            "Page.navigate",
+            json!({ "url": url }),
+            deadline,
+        )?;
+        if let Some(error) = navigation["errorText"].as_str() {
+            bail!("can't load `{}`: {}", url, error);
+        }
+        self.wait_event(session, "Page.loadEventFired", deadline)?;
+
+        // the main document response carries the status code
+        let status = self
+            .events
+            .iter()
+            .find(|event| {
+                event["method"] == "Network.responseReceived"
+                    && event["sessionId"] == session
+                    && event["params"]["type"] == "Document"
+                    && event["params"]["frameId"] == navigation["frameId"]
+            })
+            .and_then(|event| event["params"]["response"]["status"].as_u64())
+            .unwrap_or(200) as u16;
+
+        for action in &target.actions {
+            self.run_action(session, action, deadline)
+                .with_context(|| format!("browser action on `{}` failed", url))?;
+        }
+
+        if let Some(scroll) = pagination.and_then(|pagination| pagination.infinite_scroll.as_ref())
+        {
+            self.scroll_items(session, scroll, deadline)
+                .with_context(|| format!("infinite scroll on `{}` failed", url))?;
+        }
+
+        if let Some(selector) = &config.wait_for {
+            self.wait_for(session, selector, deadline)
+                .with_context(|| format!("`{}` never finished rendering", url))?;
+        }
+        if let Some(wait) = config.wait {
+            sleep(Duration::from_millis(wait));
+        }
+
+        let mut captured = IndexMap::new();
+        for (name, capture) in &config.capture {
+            match self.capture(session, &capture.url, deadline)? {
+                Some(body) => {
+                    captured.insert(name.clone(), body);
+                }
+                None => warn!("`{}` loaded no response matching `{}`", url, capture.url),
+            }
+        }
+
+        let serialize = if config.pierce_shadow {
+            PIERCE_SHADOW
+        } else {
+            "document.documentElement.outerHTML"
+        };
+        let html = self.evaluate(session, serialize, deadline)?;
+        let final_url = self.evaluate(session, "location.href", deadline)?;
+
+        Ok(Page {
+            html: html.as_str().unwrap_or_default().to_string(),
+            final_url: final_url.as_str().unwrap_or(url).to_string(),
+            status,
+            captured,
+            ..Default::default()
+        })
+    }
+
+    /// Applies the viewport, locale, timezone and stealth options to a tab
+    fn emulate(
+        &mut self,
+        session: &str,
+        config: &BrowserConfig,
+        deadline: Instant,
+    ) -> AnyhowResult<()> {
+        if let Some(viewport) = &config.viewport {
+            let metrics = json!({
+                "width": viewport.width,
+                "height": viewport.height,
+                "deviceScaleFactor": 1,
+                "mobile": false,
+            });
+            self.call(
+                Some(session),
+                "Emulation.setDeviceMetricsOverride",
+                metrics,
+                deadline,
+            )?;
+        }
+        if let Some(locale) = &config.locale {
+            let locale = json!({ "locale": locale });
+            self.call(
+                Some(session),
+                "Emulation.setLocaleOverride",
+                locale,
+                deadline,
+            )?;
+        }
+        if let Some(timezone) = &config.timezone {
+            let timezone = json!({ "timezoneId": timezone });
+            self.call(
+                Some(session),
+                "Emulation.setTimezoneOverride",
+                timezone,
+                deadline,
+            )?;
+        }
+
+        if config.stealth {
+            // the default user agent says `HeadlessChrome`
+            let version = self.call(None, "Browser.getVersion", json!({}), deadline)?;
+            let user_agent = version["userAgent"]
+                .as_str()
+                .unwrap_or_default()
+                .replace("HeadlessChrome", "Chrome");
+            let mut user_agent = json!({ "userAgent": user_agent });
+            if let Some(locale) = &config.locale {
+                user_agent["acceptLanguage"] = locale.as_str().into();
+            }
+            self.call(
+                Some(session),
+                "Network.setUserAgentOverride",
+                user_agent,
+                deadline,
+            )?;
+        }
+        for script in init_scripts(config) {
+            self.call(
+                Some(session),
+                "Page.addScriptToEvaluateOnNewDocument",
+                json!({ "source": script }),
+                deadline,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn run_action(
+        &mut self,
+        session: &str,
+        action: &BrowserAction,
+        deadline: Instant,
+    ) -> AnyhowResult<()> {
+        match action {
+            BrowserAction::Click(selector) => {
+                self.wait_for(session, selector, deadline)?;
+                self.evaluate(session, &click_script(selector), deadline)?;
+            }
+            BrowserAction::Type { selector, text } => {
+                self.wait_for(session, selector, deadline)?;
+                self.evaluate(session, &focus_script(selector), deadline)?;
+                // typed as user input so the page's key and input handlers run
+                self.call(
+                    Some(session),
+                    "Input.insertText",
+                    json!({ "text": text }),
+                    deadline,
+                )?;
+            }
+            BrowserAction::Scroll(times) => {
+                for _ in 0..*times {
+                    self.evaluate(
+                        session,
+                        "window.scrollTo(0, document.body.scrollHeight)",
+                        deadline,
+                    )?;
+                    sleep(SCROLL_PAUSE);
+                }
+            }
+            BrowserAction::WaitFor(selector) => self.wait_for(session, selector, deadline)?,
+            BrowserAction::Wait(wait) => sleep(Duration::from_millis(*wait)),
+        }
+
+        Ok(())
+    }
+
+    /// Scrolls to the bottom until the item count stops growing or a limit is hit
+    fn scroll_items(
+        &mut self,
+        session: &str,
+        scroll: &InfiniteScroll,
+        deadline: Instant,
+    ) -> AnyhowResult<()> {
+        let count = format!("document.querySelectorAll({}).length", json!(scroll.items));
+        let mut items = self
+            .evaluate(session, &count, deadline)?
+            .as_u64()
+            .unwrap_or(0);
+
+        for scrolls in 0..scroll.max_scrolls {
+            if scroll
+                .max_items
+                .is_some_and(|max_items| items >= max_items as u64)
+            {
+                break;
+            }
+
+            self.evaluate(
+                session,
+                "window.scrollTo(0, document.body.scrollHeight)",
+                deadline,
+            )?;
+
+            let scroll_deadline =
+                deadline.min(Instant::now() + Duration::from_millis(scroll.scroll_timeout));
+            let previous = items;
+            while items == previous && Instant::now() + POLL_INTERVAL <= scroll_deadline {
+                sleep(POLL_INTERVAL);
+                items = self
+                    .evaluate(session, &count, deadline)?
+                    .as_u64()
+                    .unwrap_or(0);
+            }
+
+            if items == previous {
+                debug!("no new items after {} scrolls", scrolls + 1);
+                break;
+            }
+        }
+
+        info!("infinite scroll loaded {} items", items);
+        Ok(())
+    }
+
+    /// JSON body of the first response whose url contains `pattern`
+    fn capture(
+        &mut self,
+        session: &str,
+        pattern: &str,
+        deadline: Instant,
+    ) -> AnyhowResult<Option<Value>> {
+        let request_id = self
+            .events
+            .iter()
+            .find(|event| {
+                event["method"] == "Network.responseReceived"
+                    && event["sessionId"] == session
+                    && event["params"]["response"]["url"]
+                        .as_str()
+                        .is_some_and(|url| url.contains(pattern))
+            })
+            .map(|event| event["params"]["requestId"].clone());
+        let Some(request_id) = request_id else {
+            return Ok(None);
+        };
+
+        let body = self.call(
+            Some(session),
+            "Network.getResponseBody",
+            json!({ "requestId": request_id }),
+            deadline,
+        )?;
+        if body["base64Encoded"] == true {
+            bail!("response matching `{}` is not JSON", pattern);
+        }
+
+        let body = body["body"].as_str().unwrap_or_default();
+        serde_json::from_str(body)
+            .map(Some)
+            .with_context(|| format!("response matching `{}` is not JSON", pattern))
+    }
+
+    /// Polls until `selector` matches an element
+    fn wait_for(&mut self, session: &str, selector: &str, deadline: Instant) -> AnyhowResult<()> {
+        let matched = matches_script(selector);
+
+        while self.evaluate(session, &matched, deadline)? != json!(true) {
+            if Instant::now() + POLL_INTERVAL > deadline {
+                bail!("`{}` did not appear in time", selector);
+            }
+            sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    /// Runs `expression` in the page and returns its JSON value
+    fn evaluate(
+        &mut self,
+        session: &str,
+        expression: &str,
+        deadline: Instant,
+    ) -> AnyhowResult<Value> {
+        let evaluated = self.call(
+            Some(session),
+            "Runtime.evaluate",
+            json!({ "expression": expression, "returnByValue": true, "awaitPromise": true }),
+            deadline,
+        )?;
+
+        if let Some(exception) = evaluated.get("exceptionDetails") {
+            bail!("script failed in the page: {}", exception["text"]);
+        }
+
+        Ok(evaluated["result"]["value"].clone())
+    }
+
+    /// Sends a DevTools command and waits for its result
+    fn call(
+        &mut self,
+        session: Option<&str>,
+        method: &str,
+        params: Value,
+        deadline: Instant,
+    ) -> AnyhowResult<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        let mut command = json!({ "id": id, "method": method, "params": params });
+        if let Some(session) = session {
+            command["sessionId"] = session.into();
+        }
+        self.socket
+            .send(Message::Text(command.to_string()))
+            .context("lost connection to the browser")?;
+
+        loop {
+            let message = self.read(deadline)?;
+
+            if message["id"] == id {
+                if let Some(error) = message.get("error") {
+                    bail!("browser command `{}` failed: {}", method, error["message"]);
+                }
+                return Ok(message["result"].clone());
+            }
+            if message.get("method").is_some() {
+                self.events.push_back(message);
+            }
+        }
+    }
+
+    fn wait_event(
+        &mut self,
+        session: &str,
+        method: &str,
+        deadline: Instant,
+    ) -> AnyhowResult<Value> {
+        let matches = |event: &Value| event["method"] == method && event["sessionId"] == session;
+
+        if let Some(index) = self.events.iter().position(matches) {
+            return Ok(self.events.remove(index).expect("index is in bounds"));
+        }
+
+        loop {
+            let message = self.read(deadline)?;
+
+            if matches(&message) {
+                return Ok(message);
+            }
+            if message.get("method").is_some() {
+                self.events.push_back(message);
+            }
+        }
+    }
+
+    fn read(&mut self, deadline: Instant) -> AnyhowResult<Value> {
+        loop {
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+                .context("timed out waiting for the browser")?;
+            if let MaybeTlsStream::Plain(stream) = self.socket.get_mut() {
+                stream.set_read_timeout(Some(remaining))?;
+            }
+
+            match self.socket.read() {
+                Ok(Message::Text(text)) => {
+                    return serde_json::from_str(&text).context("invalid DevTools message")
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(error))
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(error) => return Err(error).context("lost connection to the browser"),
+            }
+        }
+    }
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        let _ = self.socket.close(None);
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.profile);
+    }
+}
+
+/// Waits for the DevTools endpoint Chrome prints on stderr and connects to it
+fn connect(process: &mut Child, executable: &str) -> AnyhowResult<Socket> {
+    let mut stderr = BufReader::new(process.stderr.take().expect("stderr is piped"));
+
+    let endpoint = loop {
+        let mut line = String::new();
+        if stderr.read_line(&mut line)? == 0 {
+            bail!("browser `{}` exited before it was ready", executable);
+        }
+        if let Some(endpoint) = line.trim().strip_prefix("DevTools listening on ") {
+            break endpoint.to_string();
+        }
+    };
+
+    // keep draining stderr so the browser never blocks on a full pipe
+    thread::spawn(move || {
+        stderr
+            .lines()
+            .map_while(Result::ok)
+            .for_each(|line| debug!("browser: {}", line));
+    });
+
+    let (socket, _) = tungstenite::connect(&endpoint)
+        .with_context(|| format!("can't connect to the browser at `{}`", endpoint))?;
+    info!("browser started, DevTools listening on {}", endpoint);
+
+    Ok(socket)
+}
+
+/// Scripts run in every new document of a tab, before the page's own
+fn init_scripts(config: &BrowserConfig) -> Vec<&'static str> {
+    let mut scripts = vec![];
+    if config.hide_webdriver || config.stealth {
+        scripts.push(HIDE_WEBDRIVER);
+    }
+    if config.stealth {
+        scripts.push(STEALTH);
+    }
+    scripts
+}
+
+/// Clicks the first element matching `selector`, scrolled into view first
+fn click_script(selector: &str) -> String {
+    format!(
+        "(() => {{ const element = document.querySelector({}); element.scrollIntoView(); element.click(); }})()",
+        json!(selector)
+    )
+}
+
+fn focus_script(selector: &str) -> String {
+    format!("document.querySelector({}).focus()", json!(selector))
+}
+
+fn matches_script(selector: &str) -> String {
+    format!("document.querySelector({}) !== null", json!(selector))
+}
+
+fn find_executable() -> AnyhowResult<String> {
+    if let Ok(chrome) = env::var("CHROME") {
+        return Ok(chrome);
+    }
+
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&path)
+        .flat_map(|dir| EXECUTABLES.map(|name| dir.join(name)))
+        .find(|candidate| candidate.is_file())
+        .map(|found| found.to_string_lossy().into_owned())
+        .context("no Chrome/Chromium found, set `browser.executable` or `$CHROME`")
+}
+
+/// Output fields filled from the captured responses, `null` when nothing was captured
+pub fn captured_fields(
+    capture: &IndexMap<String, Capture>,
+    captured: &IndexMap<String, Value>,
+) -> AnyhowResult<ReturnedData> {
+    capture
+        .iter()
+        .map(|(name, capture)| {
+            let value = match captured.get(name) {
+                None => Value::Null,
+                Some(body) => match jsonpath_lib::select(body, &capture.path)
+                    .map_err(|error| anyhow!("invalid JSONPath `{}`: {}", capture.path, error))?
+                    .as_slice()
+                {
+                    [] => Value::Null,
+                    [single] => (*single).clone(),
+                    many => many.iter().map(|value| (*value).clone()).collect(),
+                },
+            };
+
+            Ok((name.clone(), ReturnedDataItem::JsonItem(value)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_test() {
+        let selector = r#"a[title="Tom's"]"#;
+        assert_eq!(
+            focus_script(selector),
+            r#"document.querySelector("a[title=\"Tom's\"]").focus()"#
+        );
+        assert_eq!(
+            matches_script("#more"),
+            r##"document.querySelector("#more") !== null"##
+        );
+        assert!(click_script("#more").contains(r##"document.querySelector("#more")"##));
+
+        let config = |yaml: &str| serde_yaml::from_str::<BrowserConfig>(yaml).unwrap();
+        assert!(init_scripts(&config("{}")).is_empty());
+        assert_eq!(
+            init_scripts(&config("{ hideWebdriver: true }")),
+            [HIDE_WEBDRIVER]
+        );
+        assert_eq!(
+            init_scripts(&config("{ stealth: true }")),
+            [HIDE_WEBDRIVER, STEALTH]
+        );
+    }
+
+    #[test]
+    fn captured_fields_test() {
+        let capture = serde_yaml::from_str::<IndexMap<String, Capture>>(
+            "{ price: { url: /api/price, path: $.price }, stock: { url: /api/stock, path: $.stock } }",
+        )
+        .unwrap();
+        let captured = IndexMap::from([("price".to_string(), json!({ "price": 5 }))]);
+
+        let fields = captured_fields(&capture, &captured).unwrap();
+        assert_eq!(fields["price"], ReturnedDataItem::JsonItem(json!(5)));
+        assert_eq!(fields["stock"], ReturnedDataItem::JsonItem(Value::Null));
+    }
+}
@@ -0,0 +1,61 @@
+use url::Url;
+
+use crate::types::CanonicalizeConfig;
+
+/// The url with its fragment and tracking parameters removed and the
+/// remaining parameters sorted; relative segments and the host's case are
+/// normalized by parsing. Urls that don't parse are kept as-is
+pub fn canonicalize(url: &str, config: &CanonicalizeConfig) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    parsed.set_fragment(None);
+
+    let mut params = parsed
+        .query_pairs()
+        .filter(|(name, _)| !config.strip_params.iter().any(|strip| matches(strip, name)))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if config.sort_params {
+        params.sort();
+    }
+
+    if params.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(params);
+    }
+
+    parsed.to_string()
+}
+
+/// `utm_*` matches every parameter starting with `utm_`
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canonical::canonicalize;
+    use crate::types::CanonicalizeConfig;
+
+    #[test]
+    fn canonicalize_test() {
+        let config = serde_yaml::from_str::<CanonicalizeConfig>("{}").unwrap();
+
+        assert_eq!(
+            canonicalize(
+                "HTTPS://Example.com/a/../shop?page=2&utm_source=x&fbclid=y&color=red#reviews",
+                &config
+            ),
+            "https://example.com/shop?color=red&page=2"
+        );
+        assert_eq!(
+            canonicalize("https://example.com/?utm_medium=mail", &config),
+            "https://example.com/"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{Context, Result as AnyhowResult};
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING};
+use reqwest::{ResponseBuilderExt, Url};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// HTTP requests of a run and their responses, recorded with `--record` and
+/// answered from with `--replay`
+pub enum Cassette {
+    Record {
+        path: PathBuf,
+        interactions: Mutex<Vec<Interaction>>,
+    },
+    Replay {
+        interactions: Mutex<Vec<Interaction>>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct RecordedResponse {
+    status: u16,
+    /// url after redirects
+    url: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Cassette {
+    pub fn record(path: &Path) -> Self {
+        Cassette::Record {
+            path: path.to_path_buf(),
+            interactions: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn replay(path: &Path) -> AnyhowResult<Self> {
+        let file = read_to_string(path)
+            .with_context(|| format!("could not read cassette `{}`", path.display()))?;
+        let interactions = serde_json::from_str(&file)
+            .with_context(|| format!("invalid cassette `{}`", path.display()))?;
+
+        Ok(Cassette::Replay {
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Cassette::Replay { .. })
+    }
+
+    /// Replays the recorded response of the request, or sends it with
+    /// `execute` and records the response
+    pub fn send(
+        &self,
+        request: Request,
+        execute: impl FnOnce(Request) -> reqwest::Result<Response>,
+    ) -> AnyhowResult<Response> {
+        let recorded = RecordedRequest::from(&request);
+
+        match self {
+            Cassette::Replay { interactions } => {
+                // the first unused match, so repeated requests replay in order
+                let mut interactions = interactions.lock().unwrap();
+                let index = interactions
+                    .iter()
+                    .position(|interaction| interaction.request == recorded)
+                    .with_context(|| {
+                        format!(
+                            "`{} {}` is not in the cassette",
+                            recorded.method, recorded.url
+                        )
+                    })?;
+
+                interactions.remove(index).response.into_response()
+            }
+            Cassette::Record { interactions, .. } => {
+                let response = RecordedResponse::read(execute(request)?)?;
+
+                interactions.lock().unwrap().push(Interaction {
+                    request: recorded,
+                    response: response.clone(),
+                });
+                response.into_response()
+            }
+        }
+    }
+
+    /// Writes the recorded interactions, in the order they were sent
+    pub fn save(&self) -> AnyhowResult<()> {
+        let Cassette::Record { path, interactions } = self else {
+            return Ok(());
+        };
+
+        let dest = File::create(path)
+            .with_context(|| format!("can't create cassette `{}`", path.display()))?;
+        serde_json::to_writer_pretty(dest, &*interactions.lock().unwrap())
+            .context("can't write cassette")
+    }
+}
+
+impl From<&Request> for RecordedRequest {
+    fn from(request: &Request) -> Self {
+        RecordedRequest {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|body| String::from_utf8_lossy(body).into_owned()),
+        }
+    }
+}
+
+impl RecordedResponse {
+    fn read(response: Response) -> AnyhowResult<Self> {
+        Ok(RecordedResponse {
+            status: response.status().as_u16(),
+            url: response.url().to_string(),
+            headers: response
+                .headers()
+                .iter()
+                // the body is stored decoded and whole
+                .filter(|(name, _)| {
+                    ![CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING].contains(name)
+                })
+                .map(|(name, value)| {
+                    let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                    (name.to_string(), value)
+                })
+                .collect(),
+            body: response.text().context("can't get url content")?,
+        })
+    }
+
+    fn into_response(self) -> AnyhowResult<Response> {
+        let url = Url::parse(&self.url)
+            .with_context(|| format!("invalid url `{}` in cassette", self.url))?;
+
+        let mut builder = http::Response::builder().status(self.status).url(url);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+
+        Ok(Response::from(builder.body(self.body)?))
+    }
+}
+
+/// A cassette answering GETs from `responses`, `(url, status, body)` in the
+/// order they are requested
+#[cfg(test)]
+pub(crate) fn replaying(responses: &[(&str, u16, &str)]) -> Cassette {
+    let interactions = responses
+        .iter()
+        .map(|(url, status, body)| {
+            serde_json::json!({
+                "request": { "method": "GET", "url": url },
+                "response": { "status": status, "url": url, "headers": [], "body": body },
+            })
+        })
+        .collect::<Vec<_>>();
+    let interactions = serde_json::from_value(serde_json::json!(interactions)).unwrap();
+
+    Cassette::Replay {
+        interactions: Mutex::new(interactions),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+    #[test]
+    fn replay_test() {
+        let interaction = |status| Interaction {
+            request: RecordedRequest {
+                method: "GET".to_string(),
+                url: "https://example.com/".to_string(),
+                body: None,
+            },
+            response: RecordedResponse {
+                status,
+                url: "https://example.com/".to_string(),
+                headers: vec![],
+                body: String::new(),
+            },
+        };
+        let cassette = Cassette::Replay {
+            interactions: Mutex::new(vec![interaction(503), interaction(200)]),
+        };
+        let send = || {
+            let request = Request::new(Method::GET, "https://example.com/".parse().unwrap());
+            cassette.send(request, |_| unreachable!("replays never send"))
+        };
+
+        assert_eq!(send().unwrap().status(), 503);
+        assert_eq!(send().unwrap().status(), 200);
+        assert!(send().is_err());
+    }
+}
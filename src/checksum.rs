@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::ArgEnum;
+use ring::signature::Ed25519KeyPair;
+use sha2::{Digest, Sha256, Sha512};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Checksum {
+    Sha256,
+    Sha512,
+}
+
+impl Checksum {
+    fn extension(self) -> &'static str {
+        match self {
+            Checksum::Sha256 => "sha256",
+            Checksum::Sha512 => "sha512",
+        }
+    }
+
+    fn digest(self, content: &[u8]) -> String {
+        match self {
+            Checksum::Sha256 => hex::encode(Sha256::digest(content)),
+            Checksum::Sha512 => hex::encode(Sha512::digest(content)),
+        }
+    }
+}
+
+/// Writes `<output>.sha256` (or `.sha512`) in the format `sha256sum -c`
+/// checks, and `<output>.sig`, the base64 Ed25519 signature of the output by
+/// the PKCS#8 `sign_key`
+pub fn write_sidecars(
+    output: &Path,
+    checksum: Option<Checksum>,
+    sign_key: Option<&Path>,
+) -> AnyhowResult<()> {
+    let content =
+        fs::read(output).with_context(|| format!("can't read output `{}`", output.display()))?;
+    let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+
+    if let Some(checksum) = checksum {
+        let line = format!("{}  {}\n", checksum.digest(&content), file_name);
+        write_sidecar(output, checksum.extension(), line)?;
+    }
+
+    if let Some(sign_key) = sign_key {
+        let key = fs::read(sign_key)
+            .with_context(|| format!("can't read signing key `{}`", sign_key.display()))?;
+        let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&key)
+            .map_err(|error| anyhow!("invalid signing key `{}`: {}", sign_key.display(), error))?;
+
+        let signature = STANDARD.encode(key.sign(&content));
+        write_sidecar(output, "sig", format!("{}\n", signature))?;
+    }
+
+    Ok(())
+}
+
+fn write_sidecar(output: &Path, extension: &str, content: String) -> AnyhowResult<()> {
+    let path = sidecar_path(output, extension);
+    fs::write(&path, content).with_context(|| format!("can't write `{}`", path.display()))
+}
+
+/// `out.json` and `sha256` give `out.json.sha256`
+fn sidecar_path(output: &Path, extension: &str) -> PathBuf {
+    let mut path = OsString::from(output.as_os_str());
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_test() {
+        assert_eq!(
+            Checksum::Sha256.digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sidecar_path(Path::new("out/products.json.gz"), "sha256"),
+            PathBuf::from("out/products.json.gz.sha256")
+        );
+    }
+}
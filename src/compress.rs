@@ -0,0 +1,104 @@
+use clap::ArgEnum;
+use flate2::write::GzEncoder;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// `.gz` and `.zst` outputs are compressed without `--compress`
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Some(Compression::Gzip),
+            Some("zst") => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Compression::Gzip => "application/gzip",
+            Compression::Zstd => "application/zstd",
+        }
+    }
+}
+
+/// Compresses what is written through it, as it is written
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(compression: Option<Compression>, writer: W) -> io::Result<Self> {
+        Ok(match compression {
+            None => Encoder::Plain(writer),
+            Some(Compression::Gzip) => {
+                Encoder::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => Encoder::Zstd(zstd::Encoder::new(writer, 0)?),
+        })
+    }
+
+    /// Writes the end of the compressed stream
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(mut writer) => writer.flush().map(|_| writer),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn encoder_test() {
+        let compress = |compression| {
+            let mut encoder = Encoder::new(Some(compression), vec![]).unwrap();
+            encoder.write_all(b"{\"title\": \"Lamp\"}").unwrap();
+            encoder.finish().unwrap()
+        };
+
+        let mut gzip = String::new();
+        GzDecoder::new(&compress(Compression::Gzip)[..])
+            .read_to_string(&mut gzip)
+            .unwrap();
+        assert_eq!(gzip, "{\"title\": \"Lamp\"}");
+        assert_eq!(
+            zstd::decode_all(&compress(Compression::Zstd)[..]).unwrap(),
+            b"{\"title\": \"Lamp\"}"
+        );
+        assert_eq!(
+            Compression::of(Path::new("out/products.json.zst")),
+            Some(Compression::Zstd)
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result as AnyhowResult};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::fs::{self, create_dir_all, read_to_string};
+use std::path::{Path, PathBuf};
+use std::thread::{self, sleep};
+use std::time::Duration;
+use validator::Validate;
+
+use crate::load_config;
+use crate::types::{ScrapeRoot, UrlConfig};
+
+/// Where claimed jobs wait while they run
+const PROCESSING: &str = "processing";
+/// Acknowledged jobs, next to their output and report
+const DONE: &str = "done";
+/// Jobs that failed, next to a `.error` file with the reason
+const FAILED: &str = "failed";
+
+/// A job file dropped in the watched directory
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Job {
+    config: JobConfig,
+
+    /// replace the config's `url` and `urls`
+    #[serde(default)]
+    urls: Vec<UrlConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum JobConfig {
+    /// a config file, kept outside the watched directory
+    Path(PathBuf),
+    Inline(Box<ScrapeRoot>),
+}
+
+/// Where a job writes its results
+pub struct JobPaths {
+    pub output: PathBuf,
+    pub report: PathBuf,
+}
+
+impl Job {
+    /// The validated config of the job, with its urls
+    fn load(path: &Path) -> AnyhowResult<ScrapeRoot> {
+        let file = read_to_string(path)
+            .with_context(|| format!("could not read job `{}`", path.display()))?;
+        let job = serde_yaml::from_str::<Job>(&file).context("invalid job")?;
+
+        let mut config = match job.config {
+            JobConfig::Path(config) => load_config(&config)?,
+            JobConfig::Inline(config) => *config,
+        };
+        if !job.urls.is_empty() {
+            config.config.url = None;
+            config.config.urls = job.urls;
+        }
+        config.validate().context("invalid config")?;
+
+        Ok(config)
+    }
+}
+
+/// Runs the job files (`.yml`, `.yaml` or `.json`) put into `dir` with
+/// `workers` at a time, checking for new ones every `poll` milliseconds,
+/// until killed. A job is claimed by moving it to `processing/` and acked by
+/// moving it to `done/` or `failed/`, so several daemons can share a directory
+pub fn watch(
+    dir: &Path,
+    workers: usize,
+    poll: u64,
+    run: impl Fn(ScrapeRoot, &str, &JobPaths) -> AnyhowResult<()> + Sync,
+) -> AnyhowResult<()> {
+    for name in [PROCESSING, DONE, FAILED] {
+        let path = dir.join(name);
+        create_dir_all(&path)
+            .with_context(|| format!("can't create job directory `{}`", path.display()))?;
+    }
+    requeue(dir)?;
+    info!("watching `{}` for jobs", dir.display());
+
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| loop {
+                match claim(dir) {
+                    Some(job) => process(dir, &job, &run),
+                    None => sleep(Duration::from_millis(poll)),
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// Jobs left in `processing/` by a daemon that was stopped are pending again
+fn requeue(dir: &Path) -> AnyhowResult<()> {
+    for entry in fs::read_dir(dir.join(PROCESSING))? {
+        let path = entry?.path();
+        if let Some(name) = path.file_name() {
+            warn!("requeuing interrupted job `{}`", path.display());
+            fs::rename(&path, dir.join(name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the first pending job, by name, into `processing/`; another worker
+/// or daemon may win the rename, the next job is tried then
+fn claim(dir: &Path) -> Option<PathBuf> {
+    let mut pending = fs::read_dir(dir)
+        .map_err(|error| warn!("can't list jobs: {}", error))
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && is_job(path))
+        .collect::<Vec<_>>();
+    pending.sort();
+
+    pending.into_iter().find_map(|path| {
+        let claimed = dir.join(PROCESSING).join(path.file_name()?);
+        fs::rename(&path, &claimed).ok()?;
+        Some(claimed)
+    })
+}
+
+fn is_job(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yml" | "yaml" | "json")
+    )
+}
+
+fn process(dir: &Path, job: &Path, run: &impl Fn(ScrapeRoot, &str, &JobPaths) -> AnyhowResult<()>) {
+    let (Some(file_name), Some(name)) = (job.file_name(), job.file_stem()) else {
+        return;
+    };
+    let name = name.to_string_lossy();
+    info!("running job `{}`", name);
+
+    let paths = JobPaths {
+        output: dir.join(DONE).join(format!("{}.output.json", name)),
+        report: dir.join(DONE).join(format!("{}.report.json", name)),
+    };
+    let result = Job::load(job).and_then(|config| run(config, &name, &paths));
+
+    let acked = match result {
+        Ok(()) => {
+            info!("job `{}` done", name);
+            fs::rename(job, dir.join(DONE).join(file_name))
+        }
+        Err(error) => {
+            error!("job `{}` failed: {:#}", name, error);
+            fs::write(
+                dir.join(FAILED).join(format!("{}.error", name)),
+                format!("{:#}\n", error),
+            )
+            .and_then(|_| fs::rename(job, dir.join(FAILED).join(file_name)))
+        }
+    };
+    if let Err(error) = acked {
+        error!("can't ack job `{}`: {}", name, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn claim_test() {
+        let dir = env::temp_dir().join(format!("karkinos-test-jobs-{}", std::process::id()));
+        create_dir_all(dir.join(PROCESSING)).unwrap();
+        fs::write(dir.join("b.yml"), "").unwrap();
+        fs::write(dir.join("a.json"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        assert_eq!(claim(&dir), Some(dir.join(PROCESSING).join("a.json")));
+        assert_eq!(claim(&dir), Some(dir.join(PROCESSING).join("b.yml")));
+        assert_eq!(claim(&dir), None);
+
+        requeue(&dir).unwrap();
+        assert!(dir.join("a.json").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
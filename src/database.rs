@@ -0,0 +1,241 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use indexmap::IndexMap;
+use log::info;
+
+use crate::env::interpolate;
+use crate::types::{DatabaseOutput, ReturnedData, ReturnedDataItem};
+
+/// Inserts (or upserts) the scraped records into a Postgres or MySQL table,
+/// creating the table when it does not exist yet
+pub fn insert(config: &DatabaseOutput, records: &[&ReturnedData]) -> AnyhowResult<()> {
+    let columns = columns(config, records);
+    if columns.is_empty() {
+        info!("no columns to insert into `{}`", config.table);
+        return Ok(());
+    }
+
+    let rows = records
+        .iter()
+        .map(|record| {
+            columns
+                .values()
+                .map(|field| record.get(field).map(column_value))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let url = interpolate(&config.url)?;
+    let column_names = columns.keys().cloned().collect::<Vec<_>>();
+    info!("inserting {} rows into `{}`", rows.len(), config.table);
+
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        insert_postgres(&url, config, &column_names, rows)
+    } else if url.starts_with("mysql://") {
+        insert_mysql(&url, config, &column_names, rows)
+    } else {
+        bail!("unsupported database url, expected `postgres://` or `mysql://`")
+    }
+}
+
+/// column name -> field, defaulting to every field of the records in the
+/// order they first appear
+fn columns(config: &DatabaseOutput, records: &[&ReturnedData]) -> IndexMap<String, String> {
+    if !config.columns.is_empty() {
+        return config.columns.clone();
+    }
+
+    records
+        .iter()
+        .flat_map(|record| record.keys())
+        .map(|field| (field.clone(), field.clone()))
+        .collect()
+}
+
+/// Nested lists and JSON values are stored as JSON text
+fn column_value(value: &ReturnedDataItem) -> String {
+    match value {
+        ReturnedDataItem::StringItem(value) => value.clone(),
+        _ => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+fn insert_postgres(
+    url: &str,
+    config: &DatabaseOutput,
+    columns: &[String],
+    rows: Vec<Vec<Option<String>>>,
+) -> AnyhowResult<()> {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let mut client =
+        postgres::Client::connect(url, postgres::NoTls).context("can't connect to postgres")?;
+
+    client
+        .batch_execute(&create_table_sql(config, columns, quote, "TEXT", "TEXT"))
+        .context("can't create table")?;
+
+    let placeholders = (1..=columns.len())
+        .map(|index| format!("${}", index))
+        .collect::<Vec<_>>();
+    let mut statement = insert_sql(config, columns, quote, &placeholders);
+    if !config.upsert_key.is_empty() {
+        let updates = update_columns(config, columns)
+            .map(|column| format!("{0} = EXCLUDED.{0}", quote(column)))
+            .collect::<Vec<_>>();
+        let key = config.upsert_key.iter().map(|column| quote(column));
+
+        statement.push_str(&format!(
+            " ON CONFLICT ({}) {}",
+            key.collect::<Vec<_>>().join(", "),
+            match updates.is_empty() {
+                true => "DO NOTHING".to_string(),
+                false => format!("DO UPDATE SET {}", updates.join(", ")),
+            }
+        ));
+    }
+
+    let mut transaction = client.transaction()?;
+    let statement = transaction.prepare(&statement)?;
+    for row in &rows {
+        let params = row
+            .iter()
+            .map(|value| value as &(dyn postgres::types::ToSql + Sync))
+            .collect::<Vec<_>>();
+        transaction
+            .execute(&statement, &params)
+            .context("can't insert row")?;
+    }
+
+    transaction.commit().context("can't commit rows")
+}
+
+fn insert_mysql(
+    url: &str,
+    config: &DatabaseOutput,
+    columns: &[String],
+    rows: Vec<Vec<Option<String>>>,
+) -> AnyhowResult<()> {
+    use mysql::prelude::Queryable;
+
+    let quote = |name: &str| format!("`{}`", name.replace('`', "``"));
+    let opts = mysql::Opts::from_url(url).context("invalid mysql url")?;
+    let mut conn = mysql::Conn::new(opts).context("can't connect to mysql")?;
+
+    // TEXT columns can't be part of a unique key without a prefix length
+    let create = create_table_sql(config, columns, quote, "TEXT", "VARCHAR(255)");
+    conn.query_drop(create).context("can't create table")?;
+
+    let placeholders = vec!["?".to_string(); columns.len()];
+    let mut statement = insert_sql(config, columns, quote, &placeholders);
+    if !config.upsert_key.is_empty() {
+        let updates = update_columns(config, columns)
+            .map(|column| format!("{0} = VALUES({0})", quote(column)))
+            .collect::<Vec<_>>();
+        let noop = quote(&config.upsert_key[0]);
+
+        statement.push_str(&format!(
+            " ON DUPLICATE KEY UPDATE {}",
+            match updates.is_empty() {
+                true => format!("{0} = {0}", noop),
+                false => updates.join(", "),
+            }
+        ));
+    }
+
+    let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+    transaction
+        .exec_batch(statement, rows)
+        .context("can't insert rows")?;
+
+    transaction.commit().context("can't commit rows")
+}
+
+fn create_table_sql(
+    config: &DatabaseOutput,
+    columns: &[String],
+    quote: impl Fn(&str) -> String,
+    column_type: &str,
+    key_type: &str,
+) -> String {
+    let mut definitions = columns
+        .iter()
+        .map(|column| match config.upsert_key.contains(column) {
+            true => format!("{} {}", quote(column), key_type),
+            false => format!("{} {}", quote(column), column_type),
+        })
+        .collect::<Vec<_>>();
+
+    if !config.upsert_key.is_empty() {
+        let key = config.upsert_key.iter().map(|column| quote(column));
+        definitions.push(format!("UNIQUE ({})", key.collect::<Vec<_>>().join(", ")));
+    }
+
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        quote(&config.table),
+        definitions.join(", ")
+    )
+}
+
+fn insert_sql(
+    config: &DatabaseOutput,
+    columns: &[String],
+    quote: impl Fn(&str) -> String,
+    placeholders: &[String],
+) -> String {
+    format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote(&config.table),
+        columns
+            .iter()
+            .map(|column| quote(column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        placeholders.join(", ")
+    )
+}
+
+fn update_columns<'a>(
+    config: &'a DatabaseOutput,
+    columns: &'a [String],
+) -> impl Iterator<Item = &'a String> {
+    columns
+        .iter()
+        .filter(|column| !config.upsert_key.contains(column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_table_sql_test() {
+        let config = serde_yaml::from_str::<DatabaseOutput>(
+            "{ url: 'mysql://localhost/db', table: products, upsertKey: [sku] }",
+        )
+        .unwrap();
+        let record = |fields: &[&str]| {
+            fields
+                .iter()
+                .map(|field| {
+                    (
+                        field.to_string(),
+                        ReturnedDataItem::StringItem(String::new()),
+                    )
+                })
+                .collect::<ReturnedData>()
+        };
+        let records = [&record(&["sku", "title"]), &record(&["price", "title"])];
+
+        let columns = columns(&config, &records);
+        assert_eq!(
+            columns.keys().collect::<Vec<_>>(),
+            vec!["sku", "title", "price"]
+        );
+        let columns = columns.keys().cloned().collect::<Vec<_>>();
+        let quote = |name: &str| format!("`{}`", name);
+        assert_eq!(
+            create_table_sql(&config, &columns, quote, "TEXT", "VARCHAR(255)"),
+            "CREATE TABLE IF NOT EXISTS `products` (`sku` VARCHAR(255), `title` TEXT, `price` TEXT, UNIQUE (`sku`))"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use indexmap::IndexMap;
+use reqwest::blocking::Client;
+use reqwest::header::ACCEPT;
+use reqwest::Url;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::types::{ScrapeRootConfig, Target};
+
+/// DNS record types asked for, A and AAAA
+const RECORD_TYPES: [(&str, u16); 2] = [("A", 1), ("AAAA", 28)];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DohResponse {
+    #[serde(default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+/// Addresses the http client connects to instead of asking the system
+/// resolver: `dnsOverrides`, then the hosts of the urls to scrape looked up
+/// through `doh`. The port is ignored, the url's port is used
+pub fn resolved_hosts(
+    config: &ScrapeRootConfig,
+    targets: &[Target],
+) -> AnyhowResult<IndexMap<String, Vec<SocketAddr>>> {
+    let mut resolved = config
+        .dns_overrides
+        .iter()
+        .map(|(host, ip)| {
+            let ip = ip
+                .parse::<IpAddr>()
+                .with_context(|| format!("invalid address `{}` for `{}`", ip, host))?;
+            Ok((host.to_lowercase(), vec![SocketAddr::new(ip, 0)]))
+        })
+        .collect::<AnyhowResult<IndexMap<_, _>>>()?;
+
+    let Some(doh) = &config.doh else {
+        return Ok(resolved);
+    };
+
+    let client = Client::new();
+    for target in targets {
+        let Some(host) = Url::parse(&target.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+        else {
+            continue;
+        };
+        if resolved.contains_key(&host) || host.parse::<IpAddr>().is_ok() {
+            continue;
+        }
+
+        let addresses = lookup(&client, doh, &host)?;
+        resolved.insert(host, addresses);
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves a host with a DNS-over-HTTPS server speaking the JSON API
+fn lookup(client: &Client, doh: &str, host: &str) -> AnyhowResult<Vec<SocketAddr>> {
+    let mut addresses = vec![];
+
+    for (name, record_type) in RECORD_TYPES {
+        let response = client
+            .get(doh)
+            .query(&[("name", host), ("type", name)])
+            .header(ACCEPT, "application/dns-json")
+            .send()
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("can't resolve `{}` with `{}`", host, doh))?
+            .json::<DohResponse>()
+            .with_context(|| format!("invalid DNS-over-HTTPS response from `{}`", doh))?;
+
+        addresses.extend(addresses_of(&response, record_type));
+    }
+
+    if addresses.is_empty() {
+        bail!("`{}` has no address according to `{}`", host, doh);
+    }
+    Ok(addresses)
+}
+
+/// CNAME answers are skipped, their targets come as answers of their own
+fn addresses_of(response: &DohResponse, record_type: u16) -> Vec<SocketAddr> {
+    response
+        .answer
+        .iter()
+        .filter(|answer| answer.record_type == record_type)
+        .filter_map(|answer| answer.data.parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_of_test() {
+        let response: DohResponse = serde_json::from_str(
+            r#"{"Status": 0, "Answer": [
+                {"name": "www.example.com", "type": 5, "data": "example.com."},
+                {"name": "example.com", "type": 1, "data": "93.184.215.14"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            addresses_of(&response, 1),
+            vec!["93.184.215.14:0".parse().unwrap()]
+        );
+        assert!(addresses_of(&response, 28).is_empty());
+    }
+}
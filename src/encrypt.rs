@@ -0,0 +1,143 @@
+use age::stream::StreamWriter;
+use age::{x25519, IdentityFile};
+use anyhow::{anyhow, bail, Context, Error, Result as AnyhowResult};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::iter;
+use std::str::FromStr;
+
+/// `--encrypt` value: `age:<recipient>`, an X25519 public key `age1...`
+#[derive(Clone)]
+pub enum Encryption {
+    Age(x25519::Recipient),
+}
+
+impl FromStr for Encryption {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some(("age", recipient)) => {
+                Ok(Encryption::Age(recipient.parse().map_err(|error| {
+                    anyhow!("invalid age recipient: {}", error)
+                })?))
+            }
+            _ => bail!("expected `age:<recipient>`"),
+        }
+    }
+}
+
+/// Encrypts what is written through it, as it is written
+pub enum Encrypter<W: Write> {
+    Plain(W),
+    Age(StreamWriter<W>),
+}
+
+impl<W: Write> Encrypter<W> {
+    pub fn new(encryption: Option<&Encryption>, writer: W) -> io::Result<Self> {
+        Ok(match encryption {
+            None => Encrypter::Plain(writer),
+            Some(Encryption::Age(recipient)) => {
+                let encryptor = age::Encryptor::with_recipients(iter::once(recipient as _))
+                    .map_err(io::Error::other)?;
+                Encrypter::Age(encryptor.wrap_output(writer)?)
+            }
+        })
+    }
+
+    /// Writes the last encrypted chunk
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encrypter::Plain(mut writer) => writer.flush().map(|_| writer),
+            Encrypter::Age(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encrypter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encrypter::Plain(writer) => writer.write(buf),
+            Encrypter::Age(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encrypter::Plain(writer) => writer.flush(),
+            Encrypter::Age(writer) => writer.flush(),
+        }
+    }
+}
+
+/// `value` encrypted to `encryption`, as `ENC[age:<base64>]` for the config
+pub fn encrypt_secret(value: &str, encryption: &Encryption) -> AnyhowResult<String> {
+    let mut encrypter = Encrypter::new(Some(encryption), vec![])?;
+    encrypter.write_all(value.as_bytes())?;
+    Ok(format!("ENC[age:{}]", STANDARD.encode(encrypter.finish()?)))
+}
+
+/// Decrypts the `age:<base64>` inside `ENC[...]` with the identities of the
+/// `KARKINOS_AGE_IDENTITY` variable or of the `KARKINOS_AGE_IDENTITY_FILE` file
+pub fn decrypt_secret(value: &str) -> AnyhowResult<String> {
+    let Some(encrypted) = value.strip_prefix("age:") else {
+        bail!("expected `ENC[age:<base64>]`");
+    };
+    let encrypted = STANDARD
+        .decode(encrypted)
+        .context("invalid encrypted value")?;
+
+    let identities = match (
+        env::var("KARKINOS_AGE_IDENTITY"),
+        env::var("KARKINOS_AGE_IDENTITY_FILE"),
+    ) {
+        (Ok(identity), _) => IdentityFile::from_buffer(identity.as_bytes())
+            .context("invalid `KARKINOS_AGE_IDENTITY`")?,
+        (_, Ok(path)) => File::open(&path)
+            .and_then(|file| IdentityFile::from_buffer(BufReader::new(file)))
+            .with_context(|| format!("can't read age identity file `{}`", path))?,
+        _ => bail!(
+            "`KARKINOS_AGE_IDENTITY` or `KARKINOS_AGE_IDENTITY_FILE` is needed to decrypt `ENC[...]` values"
+        ),
+    }
+    .into_identities()?;
+
+    let mut decrypted = String::new();
+    age::Decryptor::new(&encrypted[..])?
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))?
+        .read_to_string(&mut decrypted)
+        .context("invalid encrypted value")?;
+
+    Ok(decrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn encrypter_test() {
+        let identity = x25519::Identity::generate();
+        let encryption = format!("age:{}", identity.to_public())
+            .parse::<Encryption>()
+            .unwrap();
+
+        let mut encrypter = Encrypter::new(Some(&encryption), vec![]).unwrap();
+        encrypter.write_all(b"{\"title\": \"Lamp\"}").unwrap();
+        let encrypted = encrypter.finish().unwrap();
+
+        let mut decrypted = String::new();
+        age::Decryptor::new(&encrypted[..])
+            .unwrap()
+            .decrypt(iter::once(&identity as _))
+            .unwrap()
+            .read_to_string(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, "{\"title\": \"Lamp\"}");
+        assert!("pgp:ABCD".parse::<Encryption>().is_err());
+    }
+}
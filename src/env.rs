@@ -0,0 +1,75 @@
+use anyhow::{anyhow, bail, Result as AnyhowResult};
+use std::env;
+
+use crate::encrypt::decrypt_secret;
+
+/// Replaces `${NAME}` with the value of the `NAME` environment variable, then
+/// `ENC[age:...]` with the decrypted secret
+pub fn interpolate(value: &str) -> AnyhowResult<String> {
+    let value = replace(value, "${", '}', |name| {
+        env::var(name).map_err(|_| anyhow!("environment variable `{}` is not set", name))
+    })?;
+    replace(&value, "ENC[", ']', decrypt_secret)
+}
+
+/// Replaces what is between `open` and `close` with its `resolve`d value
+fn replace(
+    value: &str,
+    open: &str,
+    close: char,
+    resolve: impl Fn(&str) -> AnyhowResult<String>,
+) -> AnyhowResult<String> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find(open) {
+        let Some(end) = rest[start..].find(close) else {
+            bail!("unterminated `{}` in `{}`", open, value);
+        };
+
+        result.push_str(&rest[..start]);
+        result.push_str(&resolve(&rest[start + open.len()..start + end])?);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encrypt::{encrypt_secret, Encryption};
+    use crate::env::interpolate;
+
+    #[test]
+    fn interpolate_test() {
+        std::env::set_var("KARKINOS_TEST_TOKEN", "secret");
+
+        assert_eq!(interpolate("plain").unwrap(), "plain");
+        assert_eq!(
+            interpolate("Bearer ${KARKINOS_TEST_TOKEN}!").unwrap(),
+            "Bearer secret!"
+        );
+        assert!(interpolate("${KARKINOS_TEST_MISSING}").is_err());
+        assert!(interpolate("${KARKINOS_TEST_TOKEN").is_err());
+    }
+
+    #[test]
+    fn interpolate_secret_test() {
+        let identity = age::x25519::Identity::generate();
+        let encryption = format!("age:{}", identity.to_public())
+            .parse::<Encryption>()
+            .unwrap();
+        let secret = encrypt_secret("token", &encryption).unwrap();
+        std::env::set_var(
+            "KARKINOS_AGE_IDENTITY",
+            age::secrecy::ExposeSecret::expose_secret(&identity.to_string()),
+        );
+
+        assert_eq!(
+            interpolate(&format!("Bearer {}", secret)).unwrap(),
+            "Bearer token"
+        );
+        assert!(interpolate("ENC[age:AAAA]").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use regex::Regex;
+use std::fmt;
+
+use crate::types::ExpectConfig;
+use crate::types::Page;
+
+/// The response failed an `expect` check
+#[derive(Debug)]
+pub struct Unexpected {
+    pub url: String,
+    pub reason: String,
+    pub retry: bool,
+}
+
+impl fmt::Display for Unexpected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` failed `expect`: {}", self.url, self.reason)
+    }
+}
+
+impl std::error::Error for Unexpected {}
+
+pub fn check(config: &ExpectConfig, url: &str, page: &Page) -> Result<(), Unexpected> {
+    match failed_check(config, page) {
+        None => Ok(()),
+        Some(reason) => Err(Unexpected {
+            url: url.to_string(),
+            reason,
+            retry: config.retry,
+        }),
+    }
+}
+
+fn failed_check(config: &ExpectConfig, page: &Page) -> Option<String> {
+    if !config.status.is_empty() && !config.status.contains(&page.status) {
+        return Some(format!("status {}", page.status));
+    }
+
+    // rendered pages have no headers to check
+    let content_type = page
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value);
+    if let (Some(expected), Some(content_type)) = (&config.content_type, content_type) {
+        if !content_type
+            .to_lowercase()
+            .starts_with(&expected.to_lowercase())
+        {
+            return Some(format!("content type `{}`", content_type));
+        }
+    }
+
+    if let Some(min_length) = config.min_length {
+        if page.html.len() < min_length {
+            return Some(format!("body of {} bytes", page.html.len()));
+        }
+    }
+    if let Some(text) = config
+        .contains
+        .iter()
+        .find(|text| !page.html.contains(*text))
+    {
+        return Some(format!("body doesn't contain `{}`", text));
+    }
+    if let Some(text) = config
+        .not_contains
+        .iter()
+        .find(|text| page.html.contains(*text))
+    {
+        return Some(format!("body contains `{}`", text));
+    }
+    if let Some(pattern) = &config.matches {
+        // validated with the config
+        let regex = Regex::new(pattern).expect("valid `expect.matches`");
+        if !regex.is_match(&page.html) {
+            return Some(format!("body doesn't match `{}`", pattern));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_check_test() {
+        let config = ExpectConfig {
+            status: vec![200],
+            contains: vec![],
+            not_contains: vec!["Access denied".to_string()],
+            matches: Some("<h1>".to_string()),
+            min_length: None,
+            content_type: Some("text/html".to_string()),
+            retry: true,
+        };
+        let page = |status, html: &str| Page {
+            html: html.to_string(),
+            status,
+            headers: vec![(
+                "content-type".to_string(),
+                "text/html; charset=utf-8".to_string(),
+            )],
+            ..Default::default()
+        };
+
+        assert_eq!(failed_check(&config, &page(200, "<h1>Lamp</h1>")), None);
+        assert_eq!(
+            failed_check(&config, &page(404, "<h1>Lamp</h1>")),
+            Some("status 404".to_string())
+        );
+        assert_eq!(
+            failed_check(&config, &page(200, "<h1>Access denied</h1>")),
+            Some("body contains `Access denied`".to_string())
+        );
+        assert_eq!(
+            failed_check(&config, &page(200, "<p>Lamp</p>")),
+            Some("body doesn't match `<h1>`".to_string())
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result as AnyhowResult};
+use log::{info, warn};
+use reqwest::{StatusCode, Url};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+/// A request for a fixture, with the overrides of its query
+#[derive(Debug, PartialEq)]
+struct FixtureRequest {
+    path: String,
+    /// `_status`, answered instead of the file's status
+    status: Option<u16>,
+    /// `_delay` in milliseconds, added to `--latency`
+    delay: u64,
+}
+
+/// Serves the files of `dir` over HTTP until killed, each request waiting
+/// `latency` milliseconds first
+pub fn serve(dir: &Path, port: u16, latency: u64) -> AnyhowResult<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .with_context(|| format!("can't listen on port {}", port))?;
+    println!("serving `{}` on http://127.0.0.1:{}/", dir.display(), port);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("can't accept connection: {}", error);
+                continue;
+            }
+        };
+
+        let dir = dir.to_path_buf();
+        thread::spawn(move || {
+            if let Err(error) = respond(&dir, stream, latency) {
+                warn!("can't answer request: {:#}", error);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn respond(dir: &Path, stream: TcpStream, latency: u64) -> AnyhowResult<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // headers are not needed, but must be read before answering
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let request = parse_request(target);
+    sleep(Duration::from_millis(latency + request.delay));
+
+    let (status, content_type, body) = match file_path(dir, &request.path).map(fs::read) {
+        Some(Ok(body)) => (200, content_type(&request.path), body),
+        _ => (404, "text/plain", b"not found".to_vec()),
+    };
+    let status = request.status.unwrap_or(status);
+    info!("{} {}", status, target);
+
+    let reason = StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or_default();
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+
+    Ok(())
+}
+
+fn parse_request(target: &str) -> FixtureRequest {
+    let url = Url::parse("http://fixtures").and_then(|base| base.join(target));
+    let Ok(url) = url else {
+        return FixtureRequest {
+            path: "/".to_string(),
+            status: None,
+            delay: 0,
+        };
+    };
+
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| value.parse().ok())
+    };
+    FixtureRequest {
+        path: url.path().to_string(),
+        status: query("_status").map(|status: u64| status as u16),
+        delay: query("_delay").unwrap_or_default(),
+    }
+}
+
+/// The file a url path points to, directories serve their `index.html`;
+/// paths leaving `dir` point to nothing
+fn file_path(dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let path = dir.join(relative);
+    if path.is_dir() {
+        return Some(path.join("index.html"));
+    }
+    Some(path)
+}
+
+fn content_type(path: &str) -> &'static str {
+    match Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        _ => "text/html; charset=utf-8",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_request_test() {
+        assert_eq!(
+            parse_request("/products/page-2.html?_status=503&_delay=200&page=2"),
+            FixtureRequest {
+                path: "/products/page-2.html".to_string(),
+                status: Some(503),
+                delay: 200,
+            }
+        );
+        assert_eq!(file_path(Path::new("site"), "/../secret"), None);
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, bail, Result as AnyhowResult};
+use serde_json::{json, Value};
+
+use crate::types::{GraphQlConfig, ReturnedData, ReturnedDataItem};
+
+/// Request body of one page
+pub fn body(config: &GraphQlConfig, page: u32, cursor: &Value) -> Value {
+    let variables = config
+        .variables
+        .iter()
+        .map(|(name, value)| (name.clone(), interpolate(value, page, cursor)))
+        .collect::<serde_json::Map<_, _>>();
+
+    let mut body = json!({ "query": config.query, "variables": variables });
+    if let Some(operation_name) = &config.operation_name {
+        body["operationName"] = json!(operation_name);
+    }
+    body
+}
+
+/// `{page}` and `{cursor}` alone keep their JSON type, inside text they are
+/// replaced as text
+fn interpolate(value: &Value, page: u32, cursor: &Value) -> Value {
+    match value {
+        Value::String(text) if text == "{page}" => json!(page),
+        Value::String(text) if text == "{cursor}" => cursor.clone(),
+        Value::String(text) => {
+            let cursor = match cursor {
+                Value::String(cursor) => cursor.clone(),
+                Value::Null => String::new(),
+                cursor => cursor.to_string(),
+            };
+            json!(text
+                .replace("{page}", &page.to_string())
+                .replace("{cursor}", &cursor))
+        }
+        Value::Array(values) => values
+            .iter()
+            .map(|value| interpolate(value, page, cursor))
+            .collect(),
+        Value::Object(values) => values
+            .iter()
+            .map(|(name, value)| (name.clone(), interpolate(value, page, cursor)))
+            .collect(),
+        value => value.clone(),
+    }
+}
+
+/// Fails on the first error a GraphQL response reports
+pub fn check_errors(response: &Value) -> AnyhowResult<()> {
+    match response.get("errors").and_then(Value::as_array) {
+        Some(errors) if !errors.is_empty() => {
+            let message = errors[0]["message"].as_str().unwrap_or("unknown error");
+            bail!("GraphQL error: {}", message)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// First value the JSONPath selects, `null` when nothing
+pub fn select_first(response: &Value, path: &str) -> AnyhowResult<Value> {
+    Ok(select(response, path)?
+        .into_iter()
+        .next()
+        .unwrap_or(Value::Null))
+}
+
+/// Output fields from the responses of every page, stored as a JSON list
+pub fn fields(config: &GraphQlConfig, html: &str) -> AnyhowResult<ReturnedData> {
+    let responses = serde_json::from_str::<Vec<Value>>(html)
+        .map_err(|error| anyhow!("invalid GraphQL responses: {}", error))?;
+
+    config
+        .fields
+        .iter()
+        .map(|(name, path)| {
+            let mut values = vec![];
+            for response in &responses {
+                values.extend(select(response, path)?);
+            }
+
+            let value = match values.len() {
+                0 => Value::Null,
+                1 => values.remove(0),
+                _ => Value::Array(values),
+            };
+            Ok((name.clone(), ReturnedDataItem::JsonItem(value)))
+        })
+        .collect()
+}
+
+fn select(response: &Value, path: &str) -> AnyhowResult<Vec<Value>> {
+    let selected = jsonpath_lib::select(response, path)
+        .map_err(|error| anyhow!("invalid JSONPath `{}`: {}", path, error))?;
+
+    Ok(selected.into_iter().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+
+    #[test]
+    fn body_test() {
+        let config = GraphQlConfig {
+            query: "query($page: Int, $after: String) { items }".to_string(),
+            variables: IndexMap::from([
+                ("page".to_string(), json!("{page}")),
+                ("after".to_string(), json!("{cursor}")),
+                ("filter".to_string(), json!({"tag": "p{page}"})),
+            ]),
+            operation_name: None,
+            cursor: None,
+            has_next_page: None,
+            max_pages: 10,
+            fields: IndexMap::new(),
+        };
+
+        assert_eq!(
+            body(&config, 2, &json!("abc")),
+            json!({
+                "query": config.query,
+                "variables": {"page": 2, "after": "abc", "filter": {"tag": "p2"}},
+            })
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::{SecondsFormat, Utc};
+use log::info;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::load_config;
+use crate::output::{key_records, records, write_output};
+use crate::types::{HistoryConfig, ReturnedPage};
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS records (
+    id TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    scraped_at TEXT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (id, run_id)
+)";
+
+/// One stored version of a record
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    id: String,
+    run_id: String,
+    scraped_at: String,
+    data: Value,
+}
+
+/// A field whose value differs between two consecutive runs of a record
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Change {
+    id: String,
+    field: String,
+    from_run: String,
+    to_run: String,
+    from: Value,
+    to: Value,
+}
+
+/// Stores the records of a run, storing the same run id again replaces them
+pub fn store(config: &HistoryConfig, run_id: &str, pages: &[ReturnedPage]) -> AnyhowResult<()> {
+    let keyed = key_records(&config.key, records(config.records.as_ref(), pages)?)?;
+    let scraped_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut connection = open(&config.path)?;
+    let transaction = connection.transaction()?;
+    for (id, record) in &keyed {
+        transaction.execute(
+            "INSERT OR REPLACE INTO records (id, run_id, scraped_at, data) VALUES (?1, ?2, ?3, ?4)",
+            params![id, run_id, scraped_at, serde_json::to_string(record)?],
+        )?;
+    }
+    transaction
+        .commit()
+        .with_context(|| format!("can't write history `{}`", config.path))?;
+
+    info!("stored {} records in `{}`", keyed.len(), config.path);
+    Ok(())
+}
+
+/// Prints every stored version of the records, oldest first
+pub fn show(input: &Path, id: Option<&str>) -> AnyhowResult<()> {
+    let entries = entries(&open(&history_path(input)?)?, id)?;
+
+    write_output(None, None, None, None, &entries)
+}
+
+/// Prints the fields that changed between consecutive runs of every record
+pub fn diff(input: &Path, id: Option<&str>, field: Option<&str>) -> AnyhowResult<()> {
+    let entries = entries(&open(&history_path(input)?)?, id)?;
+    let changes = changes(&entries)
+        .into_iter()
+        .filter(|change| field.is_none_or(|field| change.field == field))
+        .collect::<Vec<_>>();
+
+    write_output(None, None, None, None, &changes)
+}
+
+fn history_path(input: &Path) -> AnyhowResult<String> {
+    let config = load_config(input)?;
+
+    config
+        .history
+        .map(|history| history.path)
+        .with_context(|| format!("`{}` has no `history`", input.display()))
+}
+
+fn open(path: &str) -> AnyhowResult<Connection> {
+    let connection =
+        Connection::open(path).with_context(|| format!("can't open history `{}`", path))?;
+    connection
+        .execute(CREATE_TABLE, [])
+        .with_context(|| format!("can't create history table in `{}`", path))?;
+
+    Ok(connection)
+}
+
+fn entries(connection: &Connection, id: Option<&str>) -> AnyhowResult<Vec<Entry>> {
+    let mut statement = connection.prepare(
+        "SELECT id, run_id, scraped_at, data FROM records
+         WHERE ?1 IS NULL OR id = ?1
+         ORDER BY id, scraped_at, run_id",
+    )?;
+
+    let rows = statement.query_map(params![id], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get::<_, String>(3)?,
+        ))
+    })?;
+
+    rows.map(|row| {
+        let (id, run_id, scraped_at, data) = row?;
+        Ok(Entry {
+            id,
+            run_id,
+            scraped_at,
+            data: serde_json::from_str(&data).context("invalid record in history")?,
+        })
+    })
+    .collect()
+}
+
+/// `entries` must be grouped by id and ordered by time
+fn changes(entries: &[Entry]) -> Vec<Change> {
+    let mut changes = vec![];
+
+    for pair in entries.windows(2) {
+        let [before, after] = pair else { continue };
+        if before.id != after.id {
+            continue;
+        }
+
+        let null = Value::Null;
+        let fields = before
+            .data
+            .as_object()
+            .into_iter()
+            .chain(after.data.as_object())
+            .flat_map(|record| record.keys());
+        let mut seen = vec![];
+        for field in fields {
+            if seen.contains(&field) {
+                continue;
+            }
+            seen.push(field);
+
+            let from = before.data.get(field).unwrap_or(&null);
+            let to = after.data.get(field).unwrap_or(&null);
+            if from != to {
+                changes.push(Change {
+                    id: after.id.clone(),
+                    field: field.clone(),
+                    from_run: before.run_id.clone(),
+                    to_run: after.run_id.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(id: &str, run_id: &str, data: Value) -> Entry {
+        Entry {
+            id: id.to_string(),
+            run_id: run_id.to_string(),
+            scraped_at: run_id.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn changes_test() {
+        let entries = vec![
+            entry("a", "1", json!({"price": "10", "name": "A"})),
+            entry("a", "2", json!({"price": "12", "name": "A"})),
+            entry("b", "1", json!({"price": "5"})),
+            entry("b", "2", json!({"price": "5", "stock": "3"})),
+        ];
+
+        assert_eq!(
+            changes(&entries),
+            vec![
+                Change {
+                    id: "a".to_string(),
+                    field: "price".to_string(),
+                    from_run: "1".to_string(),
+                    to_run: "2".to_string(),
+                    from: json!("10"),
+                    to: json!("12"),
+                },
+                Change {
+                    id: "b".to_string(),
+                    field: "stock".to_string(),
+                    from_run: "1".to_string(),
+                    to_run: "2".to_string(),
+                    from: Value::Null,
+                    to: json!("3"),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,444 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use indexmap::IndexMap;
+use log::warn;
+use serde_json::{json, Map, Value};
+use std::fs::{read_to_string, File};
+use std::io::{stdout, Write};
+use std::path::Path;
+
+use crate::types::{DataConfig, ItemConfig, Nth, CONFIG_VERSION};
+
+/// scrape-it options karkinos has no equivalent for
+const UNSUPPORTED_OPTIONS: [&str; 4] = ["how", "convert", "texteq", "closest"];
+
+/// Writes a karkinos config converted from a scrape-it options file
+pub fn import_scrape_it(
+    input: &Path,
+    url: Option<&str>,
+    output: Option<&Path>,
+) -> AnyhowResult<()> {
+    let source = read_to_string(input)
+        .with_context(|| format!("could not read file `{}`", input.display()))?;
+    let data = scrape_it(&source)?;
+
+    let mut data = serde_json::to_value(data)?;
+    strip_defaults(&mut data);
+
+    let config = json!({
+        "version": CONFIG_VERSION,
+        "config": { "url": url.unwrap_or("https://example.com") },
+        "data": data,
+    });
+
+    write_config(&config, output)
+}
+
+/// Writes a karkinos config replaying a "copy as cURL" command
+pub fn import_curl(command: &str, output: Option<&Path>) -> AnyhowResult<()> {
+    let config = json!({
+        "version": CONFIG_VERSION,
+        "config": curl(command)?,
+        "data": { "title": { "selector": "title" } },
+    });
+
+    write_config(&config, output)
+}
+
+fn write_config(config: &Value, output: Option<&Path>) -> AnyhowResult<()> {
+    let yaml = serde_yaml::to_string(config).context("can't write config")?;
+
+    match output {
+        None => stdout().write_all(yaml.as_bytes())?,
+        Some(output) => File::create(output)
+            .with_context(|| format!("can't create `{}`", output.display()))?
+            .write_all(yaml.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// Converts scrape-it options, given as JSON or as the object literal of a
+/// `scrapeIt(url, { ... })` call
+pub fn scrape_it(source: &str) -> AnyhowResult<DataConfig> {
+    let Some(object) = options_object(source) else {
+        bail!("no scrape-it options object found");
+    };
+    let options = json5::from_str::<Map<String, Value>>(object)
+        .context("can't parse scrape-it options, functions like `convert` are not supported")?;
+
+    convert_data(&options, "")
+}
+
+/// First balanced `{ ... }` in the source, skipping braces inside strings
+fn options_object(source: &str) -> Option<&str> {
+    let start = source.find('{')?;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (index, char) in source[start..].char_indices() {
+        match (quote, char) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(open), _) if open == char => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(char),
+            (None, '{') => depth += 1,
+            (None, '}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&source[start..=start + index]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn convert_data(options: &Map<String, Value>, prefix: &str) -> AnyhowResult<DataConfig> {
+    options
+        .iter()
+        .map(|(name, option)| {
+            let path = format!("{}{}", prefix, name);
+            Ok((name.clone(), convert_item(option, &path)?))
+        })
+        .collect()
+}
+
+fn convert_item(option: &Value, path: &str) -> AnyhowResult<ItemConfig> {
+    let option = match option {
+        Value::String(selector) => return Ok(item(selector)),
+        Value::Object(option) => option,
+        _ => bail!("`{}`: expected a selector or an options object", path),
+    };
+
+    for unsupported in UNSUPPORTED_OPTIONS {
+        if option.contains_key(unsupported) {
+            warn!(
+                "`{}`: scrape-it option `{}` is not supported, skipped",
+                path, unsupported
+            );
+        }
+    }
+
+    let text = |key: &str| option.get(key).and_then(Value::as_str);
+
+    if let Some(list_item) = text("listItem") {
+        let data = match option.get("data") {
+            Some(Value::Object(data)) => Some(convert_data(data, &format!("{}.", path))?),
+            _ => None,
+        };
+
+        return Ok(ItemConfig {
+            data,
+            ..item(list_item)
+        });
+    }
+
+    let mut config = item(text("selector").unwrap_or_default());
+    config.attr = text("attr").map(String::from);
+    if let Some(trim) = option.get("trim").and_then(Value::as_bool) {
+        config.trim = trim;
+    }
+    if let Some(eq) = option.get("eq").and_then(Value::as_u64) {
+        config.nth = Some(Nth::Index(eq as isize));
+    }
+
+    Ok(config)
+}
+
+/// Converts a curl command line into the `config` section
+pub fn curl(command: &str) -> AnyhowResult<Map<String, Value>> {
+    let words = shell_words(command)?;
+    let mut words = words.iter().map(String::as_str);
+
+    if words.next() != Some("curl") {
+        bail!("expected a command starting with `curl`");
+    }
+
+    let mut config = Map::new();
+    let mut headers = Map::new();
+    let mut cookies = Map::new();
+    let mut method = None;
+    let mut body: Option<String> = None;
+
+    while let Some(word) = words.next() {
+        let mut value = || {
+            words
+                .next()
+                .with_context(|| format!("`{}` is missing its value", word))
+        };
+
+        match word {
+            "-H" | "--header" => {
+                let header = value()?;
+                let (name, header_value) = header
+                    .split_once(':')
+                    .with_context(|| format!("invalid header `{}`", header))?;
+                let header_value = header_value.trim();
+
+                if name.eq_ignore_ascii_case("cookie") {
+                    parse_cookies(header_value, &mut cookies);
+                } else {
+                    headers.insert(name.to_string(), header_value.into());
+                }
+            }
+            "-b" | "--cookie" => parse_cookies(value()?, &mut cookies),
+            "-A" | "--user-agent" => {
+                headers.insert("User-Agent".to_string(), value()?.into());
+            }
+            "-e" | "--referer" => {
+                headers.insert("Referer".to_string(), value()?.into());
+            }
+            "-X" | "--request" => method = Some(value()?.to_uppercase()),
+            "-d" | "--data" | "--data-raw" | "--data-binary" | "--data-ascii"
+            | "--data-urlencode" => {
+                let data = value()?;
+                body = Some(match body {
+                    None => data.to_string(),
+                    Some(body) => format!("{}&{}", body, data),
+                });
+            }
+            "-u" | "--user" => {
+                let user = value()?;
+                let (username, password) = match user.split_once(':') {
+                    Some((username, password)) => (username, Some(password)),
+                    None => (user, None),
+                };
+                config.insert(
+                    "auth".to_string(),
+                    json!({ "basic": { "username": username, "password": password } }),
+                );
+            }
+            "-k" | "--insecure" => {
+                config.insert("tls".to_string(), json!({ "insecureSkipVerify": true }));
+            }
+            "-L" | "--location" | "-s" | "--silent" | "-i" | "--include" | "-v" | "--verbose"
+            | "--compressed" | "-g" | "--globoff" => {}
+            "--url" => {
+                config.insert("url".to_string(), value()?.into());
+            }
+            url if !url.starts_with('-') => {
+                config.insert("url".to_string(), url.into());
+            }
+            option => warn!("curl option `{}` is not supported, skipped", option),
+        }
+    }
+
+    if !config.contains_key("url") {
+        bail!("the curl command has no url");
+    }
+
+    // curl switches to POST as soon as there is a body
+    if let Some(method) = method.or_else(|| body.as_ref().map(|_| "POST".to_string())) {
+        if method != "GET" {
+            config.insert("method".to_string(), method.into());
+        }
+    }
+    if !headers.is_empty() {
+        config.insert("headers".to_string(), headers.into());
+    }
+    if !cookies.is_empty() {
+        config.insert("cookies".to_string(), cookies.into());
+    }
+    if let Some(body) = body {
+        config.insert("body".to_string(), body.into());
+    }
+
+    Ok(config)
+}
+
+fn parse_cookies(header: &str, cookies: &mut Map<String, Value>) {
+    for cookie in header.split(';') {
+        if let Some((name, value)) = cookie.trim().split_once('=') {
+            cookies.insert(name.to_string(), value.into());
+        }
+    }
+}
+
+/// Splits a command line the way bash would for the quoting devtools emit:
+/// single quotes, double quotes, `$'...'` strings and backslash line breaks
+fn shell_words(command: &str) -> AnyhowResult<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            ' ' | '\t' | '\n' | '\r' => words.extend(word.take()),
+            '\\' => match chars.next() {
+                Some('\n') | Some('\r') => {}
+                Some(escaped) => word.get_or_insert_with(String::new).push(escaped),
+                None => bail!("unexpected end of command after `\\`"),
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(char) => word.push(char),
+                        None => bail!("unterminated `'` quote"),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some('r') => word.push('\r'),
+                            Some(escaped) => word.push(escaped),
+                            None => bail!("unterminated `$'` quote"),
+                        },
+                        Some(char) => word.push(char),
+                        None => bail!("unterminated `$'` quote"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => word.push(escaped),
+                            Some('\n') => {}
+                            Some(char) => {
+                                word.push('\\');
+                                word.push(char);
+                            }
+                            None => bail!("unterminated `\"` quote"),
+                        },
+                        Some(char) => word.push(char),
+                        None => bail!("unterminated `\"` quote"),
+                    }
+                }
+            }
+            char => word.get_or_insert_with(String::new).push(char),
+        }
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+/// Drops unset and default item options so the written config stays short
+fn strip_defaults(value: &mut Value) {
+    if let Value::Object(map) = value {
+        map.retain(|key, value| match (key.as_str(), &*value) {
+            (_, Value::Null | Value::Bool(false)) => false,
+            (_, Value::Array(items)) => !items.is_empty(),
+            (_, Value::Object(fields)) => !fields.is_empty(),
+            ("trim", Value::Bool(true)) => false,
+            ("concatWith", Value::String(separator)) => separator != " ",
+            _ => true,
+        });
+        map.values_mut().for_each(strip_defaults);
+    }
+}
+
+fn item(selector: &str) -> ItemConfig {
+    ItemConfig {
+        selector: selector.to_string(),
+        selectors: vec![],
+        concat_with: " ".to_string(),
+        attr: None,
+        iframe: None,
+        when: None,
+        data: None,
+        max_items: None,
+        pagination: None,
+        meta: None,
+        link: None,
+        script_json: None,
+        zip: None,
+        trim: true,
+        nth: None,
+        count: false,
+        exists: false,
+        root: false,
+        with_page: false,
+        normalize_unicode: None,
+        regex: None,
+        compiled_regex: Default::default(),
+        compiled_selectors: Default::default(),
+        source: None,
+        slice: None,
+        remove_prefix: None,
+        remove_suffix: None,
+        truncate: None,
+        url_part: None,
+        slugify: false,
+        to_email: false,
+        to_phone: None,
+        map: IndexMap::new(),
+        map_default: None,
+        redact: None,
+        hash: None,
+        record_id: vec![],
+        unique_by: None,
+        detect_language: false,
+        languages: vec![],
+        length: false,
+        word_count: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import::{curl, scrape_it};
+    use crate::types::Nth;
+
+    #[test]
+    fn scrape_it_test() {
+        let source = r#"
+            scrapeIt("https://ionicabizau.net", {
+                title: ".header h1",
+                avatar: { selector: ".header img", attr: "src", eq: 1 },
+                articles: {
+                    listItem: ".article",
+                    data: {
+                        title: "a.article-title",
+                        url: { selector: "a.article-title", attr: "href" },
+                    },
+                },
+            })
+        "#;
+
+        let data = scrape_it(source).unwrap();
+
+        assert_eq!(data["title"].selector, ".header h1");
+        assert_eq!(data["avatar"].attr.as_deref(), Some("src"));
+        assert_eq!(data["avatar"].nth, Some(Nth::Index(1)));
+
+        let articles = data["articles"].data.as_ref().unwrap();
+        assert_eq!(data["articles"].selector, ".article");
+        assert_eq!(articles["url"].attr.as_deref(), Some("href"));
+    }
+
+    #[test]
+    fn curl_test() {
+        let command = r#"curl 'https://example.com/api?page=1' \
+  -H 'accept: text/html' \
+  -H 'cookie: session=abc; theme=dark' \
+  -H "x-token: \"quoted\"" \
+  --data-raw $'{"query":"it\'s"}' \
+  --compressed"#;
+
+        let config = curl(command).unwrap();
+
+        assert_eq!(config["url"], "https://example.com/api?page=1");
+        assert_eq!(config["method"], "POST");
+        assert_eq!(config["headers"]["accept"], "text/html");
+        assert_eq!(config["headers"]["x-token"], "\"quoted\"");
+        assert_eq!(config["cookies"]["session"], "abc");
+        assert_eq!(config["cookies"]["theme"], "dark");
+        assert_eq!(config["body"], r#"{"query":"it's"}"#);
+    }
+}
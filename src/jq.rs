@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Result as AnyhowResult};
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Native, RcIter};
+use jaq_json::Val;
+use serde_json::Value;
+use std::fmt::Debug;
+
+/// A compiled jq filter, with jq's standard library
+pub struct Filter(jaq_core::Filter<Native<Val>>);
+
+impl Filter {
+    pub fn compile(code: &str) -> AnyhowResult<Self> {
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let arena = Arena::default();
+        let modules = loader
+            .load(&arena, File { code, path: () })
+            .map_err(|errors| invalid(code, errors))?;
+
+        let filter = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|errors| invalid(code, errors))?;
+        Ok(Filter(filter))
+    }
+
+    /// The single output of the filter, several outputs are collected in a
+    /// list and none is null
+    pub fn run(&self, input: Value) -> AnyhowResult<Value> {
+        let inputs = RcIter::new(core::iter::empty());
+        let mut outputs = self
+            .0
+            .run((Ctx::new([], &inputs), Val::from(input)))
+            .map(|output| output.map(Value::from))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| anyhow!("filter failed: {}", error))?;
+
+        Ok(match outputs.len() {
+            0 => Value::Null,
+            1 => outputs.remove(0),
+            _ => Value::Array(outputs),
+        })
+    }
+}
+
+fn invalid<F, E: Debug>(code: &str, errors: Vec<(F, E)>) -> anyhow::Error {
+    let errors = errors
+        .iter()
+        .map(|(_, error)| format!("{:?}", error))
+        .collect::<Vec<_>>();
+    anyhow!("invalid filter `{}`: {}", code, errors.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn run_test() {
+        let pages = json!([
+            { "title": "Lamp", "price": "12" },
+            { "title": "Desk", "price": "90" }
+        ]);
+        let filter = Filter::compile("map(select(.price | tonumber > 50) | .title)").unwrap();
+
+        assert_eq!(filter.run(pages.clone()).unwrap(), json!(["Desk"]));
+        assert_eq!(
+            Filter::compile(".[].title").unwrap().run(pages).unwrap(),
+            json!(["Lamp", "Desk"])
+        );
+        assert!(Filter::compile("map(").is_err());
+    }
+}
@@ -0,0 +1,1202 @@
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use log::warn;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::iter;
+use std::path::Path;
+use std::sync::LazyLock;
+use url::Url;
+use validator::Validate;
+
+use crate::migrate::upgrade;
+use crate::transform::{companions, keep, record_id, transform, transform_traced, Trace};
+use crate::types::Page;
+use crate::types::{
+    parse_selector, DataConfig, ItemConfig, Nth, ReturnedData, ReturnedDataItem, ScrapeRoot,
+    ScriptJson, Source, TemplateConfig, CONFIG_VERSION,
+};
+
+#[cfg(feature = "fetch")]
+pub mod aggregate;
+pub mod alerts;
+#[cfg(feature = "fetch")]
+pub mod antibot;
+#[cfg(feature = "fetch")]
+pub mod bench;
+#[cfg(feature = "fetch")]
+pub mod browser;
+pub mod canonical;
+#[cfg(feature = "fetch")]
+pub mod cassette;
+#[cfg(feature = "fetch")]
+pub mod checksum;
+#[cfg(feature = "fetch")]
+pub mod compress;
+#[cfg(feature = "fetch")]
+pub mod daemon;
+#[cfg(feature = "fetch")]
+pub mod database;
+#[cfg(feature = "fetch")]
+pub mod dns;
+#[cfg(feature = "fetch")]
+pub mod encrypt;
+#[cfg(feature = "fetch")]
+pub mod env;
+#[cfg(feature = "fetch")]
+pub mod expect;
+#[cfg(feature = "fetch")]
+pub mod fetch;
+#[cfg(feature = "fetch")]
+pub mod fixtures;
+#[cfg(feature = "fetch")]
+pub mod graphql;
+#[cfg(feature = "fetch")]
+pub mod history;
+#[cfg(feature = "fetch")]
+pub mod import;
+#[cfg(feature = "fetch")]
+pub mod jq;
+#[cfg(feature = "fetch")]
+pub mod lint;
+pub mod migrate;
+#[cfg(feature = "fetch")]
+pub mod notify;
+#[cfg(feature = "fetch")]
+pub mod oauth2;
+pub mod output;
+#[cfg(feature = "fetch")]
+pub mod pipeline;
+#[cfg(feature = "fetch")]
+pub mod plugin;
+pub mod preprocess;
+#[cfg(feature = "fetch")]
+pub mod provenance;
+#[cfg(feature = "fetch")]
+pub mod render_cache;
+#[cfg(feature = "fetch")]
+pub mod report;
+#[cfg(feature = "fetch")]
+pub mod schema;
+#[cfg(feature = "fetch")]
+pub mod signing;
+#[cfg(feature = "fetch")]
+pub mod sigv4;
+#[cfg(feature = "fetch")]
+pub mod snapshot;
+#[cfg(feature = "fetch")]
+pub mod stream;
+#[cfg(feature = "fetch")]
+pub mod tor;
+pub mod transform;
+pub mod types;
+#[cfg(feature = "fetch")]
+pub mod upload;
+#[cfg(feature = "fetch")]
+pub mod visited;
+#[cfg(feature = "fetch")]
+pub mod warc;
+
+pub fn load_config(input: &Path) -> AnyhowResult<ScrapeRoot> {
+    let config_file = read_to_string(input)
+        .with_context(|| format!("could not read file `{}`", input.display()))?;
+
+    parse_config(&config_file)
+}
+
+/// A YAML (or JSON) config, validated
+pub fn parse_config(config: &str) -> AnyhowResult<ScrapeRoot> {
+    let config = serde_yaml::from_str::<serde_yaml::Value>(config).context("invalid config")?;
+
+    parse_config_value(config)
+}
+
+/// Parses a config, upgrading it first when written for an older version
+pub fn parse_config_value(mut config: serde_yaml::Value) -> AnyhowResult<ScrapeRoot> {
+    if upgrade(&mut config).context("invalid config")? {
+        warn!(
+            "config is in the format of an older karkinos, upgrade it to version {} with `karkinos migrate`",
+            CONFIG_VERSION
+        );
+    }
+    let config_serialized =
+        serde_yaml::from_value::<ScrapeRoot>(config).context("invalid config")?;
+
+    config_serialized.validate().context("invalid config")?;
+
+    Ok(config_serialized)
+}
+
+static META: LazyLock<Selector> = LazyLock::new(|| parse_selector("meta"));
+static LINK: LazyLock<Selector> = LazyLock::new(|| parse_selector("link[href]"));
+static BASE: LazyLock<Selector> = LazyLock::new(|| parse_selector("base[href]"));
+
+/// A template matcher with its url pattern and selector compiled
+pub type Template<'a> = (
+    &'a String,
+    &'a TemplateConfig,
+    Option<Regex>,
+    Option<Selector>,
+);
+
+pub type Templates<'a> = [Template<'a>];
+
+pub fn compile_templates(config: &ScrapeRoot) -> AnyhowResult<Vec<Template<'_>>> {
+    config
+        .templates
+        .iter()
+        .map(|(name, template)| {
+            let url = template.url.as_deref().map(Regex::new).transpose()?;
+            let selector = template
+                .selector
+                .as_deref()
+                .map(|selector| {
+                    Selector::parse(selector)
+                        .map_err(|_| anyhow!("invalid template selector `{}`", selector))
+                })
+                .transpose()?;
+            Ok((name, template, url, selector))
+        })
+        .collect()
+}
+
+/// The first template whose url pattern and selector both match the page
+pub fn match_template<'a>(
+    templates: &'a Templates,
+    url: &str,
+    document: &Document,
+) -> Option<(&'a String, &'a TemplateConfig)> {
+    templates
+        .iter()
+        .find(|(_, _, pattern, selector)| {
+            pattern.as_ref().is_none_or(|pattern| pattern.is_match(url))
+                && selector
+                    .as_ref()
+                    .is_none_or(|selector| document.html.select(selector).next().is_some())
+        })
+        .map(|(name, template, _, _)| (*name, *template))
+}
+
+/// A page parsed once, with the documents of its frames and list pages by url
+pub struct Document<'a> {
+    pub page: &'a Page,
+    pub html: Html,
+    frames: HashMap<&'a str, Html>,
+}
+
+impl<'a> Document<'a> {
+    pub fn parse(page: &'a Page) -> Self {
+        Document {
+            page,
+            html: Html::parse_fragment(&page.html),
+            frames: page
+                .frames
+                .iter()
+                .map(|(url, html)| (url.as_str(), Html::parse_document(html)))
+                .collect(),
+        }
+    }
+}
+
+/// Parses the page once, nested `data` is extracted from the matched subtrees
+pub fn populate_values(page: &Page, config: &DataConfig) -> ReturnedData {
+    populate_values_within(&Document::parse(page), config, None)
+}
+
+/// Like `populate_values`, with every field selected within the first match
+/// of `context`, and empty when nothing matches it
+pub fn populate_values_within(
+    document: &Document,
+    config: &DataConfig,
+    context: Option<&str>,
+) -> ReturnedData {
+    within_context(document, context, |element| {
+        extract(element, config, false, document)
+    })
+}
+
+/// Why each field of `config` has its value, for `--explain`: whether `when`
+/// skipped it, how many elements its selectors matched, which transform
+/// emptied its value and whether `mapDefault` replaced it
+pub fn diagnostics(document: &Document, config: &DataConfig, context: Option<&str>) -> Value {
+    within_context(document, context, |element| {
+        config
+            .iter()
+            .map(|(name, config)| {
+                let diagnostics =
+                    in_frame(element, config, false, document, |element, include_self| {
+                        field_diagnostics(element, config, include_self, document)
+                    });
+                (name.clone(), Value::Object(diagnostics))
+            })
+            .collect()
+    })
+}
+
+fn field_diagnostics(
+    element: ElementRef,
+    config: &ItemConfig,
+    include_self: bool,
+    document: &Document,
+) -> Map<String, Value> {
+    let mut diagnostics = Map::new();
+    if !when_matches(config, element, include_self) {
+        diagnostics.insert("skipped".to_string(), "when".into());
+        return diagnostics;
+    }
+
+    let compiled = config.get_selectors();
+    let selectors = if !compiled.selectors.is_empty() {
+        compiled.selectors.iter().collect()
+    } else {
+        compiled.selector.iter().collect::<Vec<_>>()
+    };
+    if !selectors.is_empty() && config.zip.is_none() && config.script_json.is_none() {
+        let matches = selectors
+            .into_iter()
+            .map(|selector| select(element, selector, include_self).count())
+            .sum::<usize>();
+        diagnostics.insert("matches".to_string(), matches.into());
+    }
+
+    let single = config.data.is_none()
+        && config.zip.is_none()
+        && config.script_json.is_none()
+        && !config.count
+        && !config.exists;
+    if single {
+        let mut trace = Trace::default();
+        let value = field_value(element, config, include_self, document);
+        transform_traced(config, value, &mut trace);
+        if let Some(step) = trace.emptied_by {
+            diagnostics.insert("emptiedBy".to_string(), step.into());
+        }
+        if trace.default {
+            diagnostics.insert("default".to_string(), "mapDefault".into());
+        }
+    }
+
+    diagnostics
+}
+
+/// Runs `f` on the first match of `context` in the page, or on the page
+fn within_context<T>(
+    document: &Document,
+    context: Option<&str>,
+    f: impl FnOnce(ElementRef) -> T,
+) -> T {
+    let Some(context) = context else {
+        return f(document.html.root_element());
+    };
+
+    match document.html.select(&parse_selector(context)).next() {
+        Some(element) => f(element),
+        None => f(Html::parse_fragment("").root_element()),
+    }
+}
+
+/// Runs `f` on the document of the field's `iframe`, or on `element`
+fn in_frame<T>(
+    element: ElementRef,
+    config: &ItemConfig,
+    include_self: bool,
+    document: &Document,
+    f: impl FnOnce(ElementRef, bool) -> T,
+) -> T {
+    let Some(iframe) = config.get_iframe_selector() else {
+        return f(element, include_self);
+    };
+
+    let frame = element
+        .select(iframe)
+        .next()
+        .and_then(|iframe| iframe.value().attr("src"))
+        .and_then(|src| document.frames.get(src));
+    match frame {
+        Some(frame) => f(frame.root_element(), false),
+        None => f(Html::parse_document("").root_element(), false),
+    }
+}
+
+/// `include_self` lets list items match their own selectors, so an item's
+/// attributes can be read without a nested element
+pub fn extract(
+    element: ElementRef,
+    config: &DataConfig,
+    include_self: bool,
+    document: &Document,
+) -> ReturnedData {
+    config
+        .iter()
+        .filter(|(_, config)| when_matches(config, element, include_self))
+        .flat_map(|(name, config)| {
+            let value = in_frame(
+                element,
+                config,
+                include_self,
+                document,
+                |element, include_self| extract_field(element, config, include_self, document),
+            );
+            let companions = companions(name, config, &value);
+
+            iter::once((name.clone(), value)).chain(companions)
+        })
+        .collect()
+}
+
+/// Whether the field's `when` allows extracting it, true without one
+fn when_matches(config: &ItemConfig, element: ElementRef, include_self: bool) -> bool {
+    let matches = |selector: &Selector| {
+        (include_self && selector.matches(&element)) || element.select(selector).next().is_some()
+    };
+    let selectors = config.get_selectors();
+
+    selectors.exists.as_ref().is_none_or(matches)
+        && !selectors.missing.as_ref().is_some_and(matches)
+}
+
+fn extract_field(
+    element: ElementRef,
+    config: &ItemConfig,
+    include_self: bool,
+    document: &Document,
+) -> ReturnedDataItem {
+    if let Some(zip) = &config.zip {
+        return records(
+            config,
+            zip,
+            zip_records(element, zip, include_self).into_iter(),
+        );
+    }
+    if let Some(script_json) = &config.script_json {
+        return ReturnedDataItem::JsonItem(script_json_value(
+            element,
+            config,
+            script_json,
+            include_self,
+        ));
+    }
+    if config.meta.is_some() || config.link.is_some() || config.source == Some(Source::Raw) {
+        return transform(config, field_value(element, config, include_self, document));
+    }
+
+    match &config.data {
+        Some(inner) => {
+            let selector = config.get_item_selector();
+            let items = select(element, selector, include_self)
+                .map(|item| extract(item, inner, true, document))
+                .chain(next_page_items(element, config, inner, document));
+            let items = match &config.nth {
+                Some(nth) => nth.pick(items),
+                None => items.collect(),
+            };
+
+            records(config, inner, items.into_iter())
+        }
+        None if config.count => {
+            let count = select(element, config.get_item_selector(), include_self).count();
+            ReturnedDataItem::JsonItem(count.into())
+        }
+        None if config.exists => {
+            let exists = select(element, config.get_item_selector(), include_self)
+                .next()
+                .is_some();
+            ReturnedDataItem::JsonItem(exists.into())
+        }
+        None => match &config.nth {
+            Some(nth @ Nth::Range(_)) if config.selectors.is_empty() => {
+                let selector = config.get_item_selector();
+                ReturnedDataItem::JsonItem(Value::Array(
+                    nth.pick(select(element, selector, include_self))
+                        .into_iter()
+                        .map(|selected| {
+                            let value = transform(config, element_value(Some(selected), config));
+                            serde_json::to_value(value).unwrap_or_default()
+                        })
+                        .collect(),
+                ))
+            }
+            _ => transform(config, field_value(element, config, include_self, document)),
+        },
+    }
+}
+
+/// The value of a single value field before its transforms
+fn field_value(
+    element: ElementRef,
+    config: &ItemConfig,
+    include_self: bool,
+    document: &Document,
+) -> String {
+    if let Some(name) = &config.meta {
+        return meta_content(element, name);
+    }
+    if let Some(rel) = &config.link {
+        return link_href(element, rel, document.page);
+    }
+    if config.source == Some(Source::Raw) {
+        return page_source(element);
+    }
+
+    let nth = config.nth.as_ref().unwrap_or(&Nth::Index(0));
+    if config.selectors.is_empty() {
+        let selector = config.get_item_selector();
+        let selected = nth.pick(select(element, selector, include_self));
+        return element_value(selected.into_iter().next(), config);
+    }
+
+    let values = config
+        .get_selectors()
+        .selectors
+        .iter()
+        .flat_map(|selector| nth.pick(select(element, selector, include_self)))
+        .map(|selected| element_value(Some(selected), config).trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect::<Vec<_>>();
+    values.join(&config.concat_with)
+}
+
+/// The value `path` selects in the JSON of the first matching script, null
+/// when there is none or it isn't JSON
+fn script_json_value(
+    element: ElementRef,
+    config: &ItemConfig,
+    script_json: &ScriptJson,
+    include_self: bool,
+) -> Value {
+    let Some(selector) = &config.get_selectors().script_json else {
+        return Value::Null;
+    };
+    let Some(script) = select(element, selector, include_self).next() else {
+        return Value::Null;
+    };
+    let json = match serde_json::from_str::<Value>(&script.text().collect::<String>()) {
+        Ok(json) => json,
+        Err(error) => {
+            warn!("`{}` is not JSON: {}", script_json.selector, error);
+            return Value::Null;
+        }
+    };
+
+    match jsonpath_lib::select(&json, &script_json.path).as_deref() {
+        Ok([]) | Err(_) => Value::Null,
+        Ok([single]) => (*single).clone(),
+        Ok(many) => many.iter().map(|value| (*value).clone()).collect(),
+    }
+}
+
+/// Items of the pages a list's `pagination.next` links lead to, fetched
+/// beforehand into the page's `frames`
+fn next_page_items(
+    element: ElementRef,
+    config: &ItemConfig,
+    inner: &DataConfig,
+    document: &Document,
+) -> Vec<ReturnedData> {
+    let (Some(pagination), Some(next)) = (&config.pagination, &config.get_selectors().next) else {
+        return vec![];
+    };
+    let selector = config.get_item_selector();
+    let next_href = |element: ElementRef| {
+        element
+            .select(next)
+            .next()
+            .and_then(|link| link.value().attr("href"))
+            .map(String::from)
+    };
+
+    let mut items = vec![];
+    let mut href = next_href(element);
+    for _ in 1..pagination.max_pages {
+        let Some(html) = href
+            .as_ref()
+            .and_then(|href| document.frames.get(href.as_str()))
+        else {
+            break;
+        };
+        items.extend(
+            html.select(selector)
+                .map(|item| extract(item, inner, true, document)),
+        );
+        href = next_href(html.root_element());
+    }
+    items
+}
+
+/// The items of a list field, filtered by `uniqueBy` and the inner filters,
+/// with their `recordId`
+fn records(
+    config: &ItemConfig,
+    inner: &DataConfig,
+    items: impl Iterator<Item = ReturnedData>,
+) -> ReturnedDataItem {
+    let mut seen = HashSet::new();
+
+    ReturnedDataItem::DataItems(
+        items
+            .filter(|item| keep(inner, item))
+            .filter(|item| {
+                config.unique_by.as_ref().is_none_or(|field| {
+                    seen.insert(serde_json::to_string(&item.get(field)).unwrap_or_default())
+                })
+            })
+            .take(config.max_items.unwrap_or(usize::MAX))
+            .map(|mut item| {
+                if !config.record_id.is_empty() {
+                    item.insert("_id".to_string(), record_id(&config.record_id, &item));
+                }
+                item
+            })
+            .collect(),
+    )
+}
+
+/// Pairs the matches of the `zip` fields by index, as many records as the
+/// field with the fewest matches
+fn zip_records(element: ElementRef, zip: &DataConfig, include_self: bool) -> Vec<ReturnedData> {
+    let columns = zip
+        .iter()
+        .map(|(name, config)| {
+            let selector = config.get_item_selector();
+            let selected = select(element, selector, include_self);
+            let selected = match &config.nth {
+                Some(nth) => nth.pick(selected),
+                None => selected.collect(),
+            };
+            let values = selected
+                .into_iter()
+                .map(|selected| transform(config, element_value(Some(selected), config)))
+                .collect::<Vec<_>>();
+
+            (name, values)
+        })
+        .collect::<Vec<_>>();
+
+    let len = columns
+        .iter()
+        .map(|(_, values)| values.len())
+        .min()
+        .unwrap_or(0);
+    if columns.iter().any(|(_, values)| values.len() != len) {
+        let lens = columns
+            .iter()
+            .map(|(name, values)| format!("{}: {}", name, values.len()))
+            .collect::<Vec<_>>();
+        warn!(
+            "zip fields matched different counts ({}), extra matches are dropped",
+            lens.join(", ")
+        );
+    }
+
+    (0..len)
+        .map(|index| {
+            columns
+                .iter()
+                .map(|(name, values)| (name.to_string(), values[index].clone()))
+                .collect()
+        })
+        .collect()
+}
+
+/// `include_self` lets the element match `selector` itself
+fn select<'a: 'b, 'b>(
+    element: ElementRef<'a>,
+    selector: &'b Selector,
+    include_self: bool,
+) -> impl Iterator<Item = ElementRef<'a>> + 'b {
+    include_self
+        .then_some(element)
+        .filter(|element| selector.matches(element))
+        .into_iter()
+        .chain(element.select(selector))
+}
+
+/// The HTML of the whole document `element` is in, for `source: raw`
+fn page_source(element: ElementRef) -> String {
+    document_element(element).html()
+}
+
+/// `content` of the first `<meta>` of the document named `name`, by its
+/// `name` or `property`
+fn meta_content(element: ElementRef, name: &str) -> String {
+    document_element(element)
+        .select(&META)
+        .find(|meta| {
+            let meta = meta.value();
+            meta.attr("name") == Some(name) || meta.attr("property") == Some(name)
+        })
+        .and_then(|meta| meta.value().attr("content"))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// `href` of the first `<link>` of the document with `rel` among its
+/// relations, resolved against the document's `<base>` and the page url
+fn link_href(element: ElementRef, rel: &str, page: &Page) -> String {
+    let document = document_element(element);
+    let Some(href) = document
+        .select(&LINK)
+        .find(|link| {
+            link.value()
+                .attr("rel")
+                .unwrap_or_default()
+                .split_ascii_whitespace()
+                .any(|relation| relation.eq_ignore_ascii_case(rel))
+        })
+        .and_then(|link| link.value().attr("href"))
+    else {
+        return String::new();
+    };
+
+    let base = document
+        .select(&BASE)
+        .next()
+        .and_then(|base| base.value().attr("href"));
+    let page_url = Url::parse(&page.final_url);
+    let base = match (page_url, base) {
+        (Ok(page_url), Some(base)) => page_url.join(base).ok(),
+        (Ok(page_url), None) => Some(page_url),
+        (Err(_), base) => base.and_then(|base| Url::parse(base).ok()),
+    };
+
+    base.and_then(|base| base.join(href).ok())
+        .map_or_else(|| href.to_string(), String::from)
+}
+
+/// The outermost element of the document `element` is in, fields like `meta`
+/// read the whole page even within list items or a `context`
+fn document_element(element: ElementRef) -> ElementRef {
+    element
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .last()
+        .unwrap_or(element)
+}
+
+/// The element's inner HTML or `attr`, empty without an element
+fn element_value(element: Option<ElementRef>, config: &ItemConfig) -> String {
+    match (element, &config.attr) {
+        (None, _) => String::new(),
+        (Some(element), None) => element.inner_html(),
+        (Some(element), Some(attr)) => element.value().attr(attr).unwrap_or("").to_string(),
+    }
+}
+
+/// True when no field extracted anything: every value is empty and every list has no items
+pub fn is_empty_result(data: &ReturnedData) -> bool {
+    data.values().all(|value| match value {
+        ReturnedDataItem::StringItem(value) => value.is_empty(),
+        ReturnedDataItem::DataItems(items) => items.iter().all(is_empty_result),
+        ReturnedDataItem::JsonItem(value) => value.is_null(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ReturnedDataItem::{DataItems, JsonItem, StringItem};
+    use crate::{
+        compile_templates, diagnostics, is_empty_result, match_template, parse_config,
+        populate_values, populate_values_within, DataConfig, Document, Page,
+    };
+
+    fn page(html: &str) -> Page {
+        Page {
+            html: html.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn populate_values_test() {
+        // From w3schools
+        let html = r#"
+            <!DOCTYPE html>
+            <html>
+               <body>
+                  <h1>The article element</h1>
+                  <article>
+                     <h2>Google Chrome</h2>
+                     <p>Google Chrome is a web browser developed by Google, released in 2008. Chrome is the world's most popular web browser today!</p>
+                  </article>
+                  <article>
+                     <h2>Mozilla Firefox</h2>
+                     <p>Mozilla Firefox is an open-source web browser developed by Mozilla. Firefox has been the second most popular web browser since January, 2018.</p>
+                  </article>
+                  <article>
+                     <h2>Microsoft Edge</h2>
+                     <p>Microsoft Edge is a web browser developed by Microsoft, released in 2015. Microsoft Edge replaced Internet Explorer.</p>
+                  </article>
+               </body>
+            </html>
+        "#;
+
+        let yaml_config = r#"
+        title:
+            selector: h1
+        articles:
+            selector: article
+            data:
+                title:
+                    selector: h2
+                description: 
+                    selector: p
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let data = populate_values(&page(html), &data_config);
+
+        assert_eq!(
+            data.get("title").unwrap().clone(),
+            StringItem(String::from("The article element"))
+        );
+        assert_eq!(data.keys().collect::<Vec<_>>(), ["title", "articles"]);
+        let articles = data.get("articles").unwrap().clone();
+        assert!(matches!(articles, DataItems { .. }));
+    }
+
+    #[test]
+    fn is_empty_result_test() {
+        let html = r#"<div><p class="empty"></p></div>"#;
+        let yaml_config = r#"
+        missing:
+            selector: h1
+        empty:
+            selector: p.empty
+        items:
+            selector: li
+            data:
+                name:
+                    selector: span
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        assert!(is_empty_result(&populate_values(&page(html), &data_config)));
+
+        let html = r#"<ul><li><span>value</span></li></ul>"#;
+        assert!(!is_empty_result(&populate_values(
+            &page(html),
+            &data_config
+        )));
+    }
+
+    #[test]
+    fn templates_test() {
+        let config = parse_config(
+            r#"
+config: { url: 'https://example.com' }
+templates:
+  product:
+    url: /p/
+    selector: .product
+    data: { title: { selector: h1 } }
+  listing:
+    selector: ul.products
+    context: main
+    data: { count: { selector: li, count: true } }
+  search:
+    url: /search
+    data: { query: { selector: input, attr: value } }
+"#,
+        )
+        .unwrap();
+        let templates = compile_templates(&config).unwrap();
+        let matched = |url: &str, html: &str| {
+            let page = page(html);
+            let document = Document::parse(&page);
+            match_template(&templates, url, &document).map(|(name, _)| name.clone())
+        };
+
+        let product = r#"<div class="product"><h1>Lamp</h1></div>"#;
+        let listing = r#"<main><ul class="products"><li>Lamp</li></ul></main>"#;
+        assert_eq!(
+            matched("https://example.com/p/1", product).as_deref(),
+            Some("product")
+        );
+        // the url matches but the selector doesn't
+        assert_eq!(
+            matched("https://example.com/p/1", listing).as_deref(),
+            Some("listing")
+        );
+        // the first matching template wins
+        assert_eq!(
+            matched("https://example.com/search?p/", listing).as_deref(),
+            Some("listing")
+        );
+        assert_eq!(
+            matched("https://example.com/search?q=lamp", "<p>none</p>").as_deref(),
+            Some("search")
+        );
+        assert_eq!(matched("https://example.com/about", "<p>About</p>"), None);
+
+        let invalid = "config: { url: 'https://example.com' }\ntemplates: { product: { url: '(', data: { title: { selector: h1 } } } }";
+        assert!(parse_config(invalid).is_err());
+    }
+
+    #[test]
+    fn iframe_test() {
+        let mut page = page(r#"<h1>Shop</h1><iframe id="reviews" src="/reviews"></iframe>"#);
+        page.frames.insert(
+            "/reviews".to_string(),
+            r#"<ul><li class="review">Great</li><li class="review">Fine</li></ul>"#.to_string(),
+        );
+        let yaml_config = r#"
+        title:
+            selector: h1
+        reviews:
+            iframe: iframe#reviews
+            selector: li.review
+            data:
+                text:
+                    selector: li
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let data = populate_values(&page, &data_config);
+
+        assert_eq!(
+            data.get("title").unwrap().clone(),
+            StringItem(String::from("Shop"))
+        );
+        let DataItems(reviews) = data.get("reviews").unwrap() else {
+            panic!("reviews is not a list");
+        };
+        assert_eq!(
+            reviews[1].get("text").unwrap().clone(),
+            StringItem(String::from("Fine"))
+        );
+    }
+
+    #[test]
+    fn when_test() {
+        let yaml_config = r#"
+        price:
+            selector: .price
+            when:
+                exists: .product
+        category:
+            selector: h1
+            when:
+                missing: .product
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let product = populate_values(
+            &page(r#"<div class="product"><span class="price">5</span></div>"#),
+            &data_config,
+        );
+        assert_eq!(product.keys().collect::<Vec<_>>(), ["price"]);
+
+        let category = populate_values(&page("<h1>Lamps</h1>"), &data_config);
+        assert_eq!(category.keys().collect::<Vec<_>>(), ["category"]);
+
+        let invalid = "config: { url: 'https://example.com' }\ndata: { list: { selector: ul, data: { sku: { selector: li, when: { exists: '[' } } } } }";
+        assert!(parse_config(invalid).is_err());
+        let invalid = "config: { url: 'https://example.com' }\ntemplates: { product: { selector: 'div >', data: { sku: { selector: li } } } }";
+        assert!(parse_config(invalid).is_err());
+    }
+
+    #[test]
+    fn selectors_test() {
+        let html = r#"<address><span class="street"> 1 Main St </span><span class="zip">10001</span></address>"#;
+        let yaml_config = r#"
+        address:
+            selectors: [.street, .city, .zip]
+            concatWith: ", "
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        assert_eq!(
+            populate_values(&page(html), &data_config)["address"],
+            StringItem(String::from("1 Main St, 10001"))
+        );
+    }
+
+    #[test]
+    fn count_and_exists_test() {
+        let html = r#"<ul class="reviews"><li>Great</li><li>Fine</li></ul>"#;
+        let yaml_config = r#"
+        reviews:
+            selector: .reviews li
+            count: true
+        has_discount:
+            selector: .badge.discount
+            exists: true
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let data = populate_values(&page(html), &data_config);
+        assert_eq!(data["reviews"], JsonItem(2.into()));
+        assert_eq!(data["has_discount"], JsonItem(false.into()));
+    }
+
+    #[test]
+    fn nth_test() {
+        let html = r#"<nav><a>Home</a><a>Lamps</a><a>Desk lamps</a></nav>
+            <table><tr><td>A</td></tr><tr><td>B</td></tr><tr><td>C</td></tr></table>"#;
+        let yaml_config = r#"
+        current:
+            selector: nav a
+            nth: -1
+        trail:
+            selector: nav a
+            nth: "..-1"
+        rows:
+            selector: tr
+            nth: even
+            data:
+                name:
+                    selector: td
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let data = populate_values(&page(html), &data_config);
+        assert_eq!(data["current"], StringItem(String::from("Desk lamps")));
+        assert_eq!(
+            data["trail"],
+            JsonItem(serde_json::json!(["Home", "Lamps"]))
+        );
+        let DataItems(rows) = &data["rows"] else {
+            panic!("rows is not a list");
+        };
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["name"], StringItem(String::from("C")));
+        assert!(serde_yaml::from_str::<DataConfig>("a: { selector: a, nth: '1..x' }").is_err());
+    }
+
+    #[test]
+    fn context_test() {
+        let html = r#"<header><h2>Sale</h2></header><main><h2>Lamp</h2></main>"#;
+        let data_config = serde_yaml::from_str::<DataConfig>("title: { selector: h2 }").unwrap();
+
+        let within = |context| {
+            populate_values_within(&Document::parse(&page(html)), &data_config, Some(context))
+        };
+        assert_eq!(within("main")["title"], StringItem(String::from("Lamp")));
+        assert!(is_empty_result(&within("#missing")));
+    }
+
+    #[test]
+    fn unique_by_test() {
+        let html = r#"<section class="featured"><a class="card" href="/lamp">Lamp</a></section>
+            <section><a class="card" href="/lamp">Lamp</a><a class="card" href="/desk">Desk</a></section>"#;
+        let yaml_config = r#"
+        products:
+            selector: .card
+            uniqueBy: url
+            data:
+                url:
+                    selector: a
+                    attr: href
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let DataItems(products) = &populate_values(&page(html), &data_config)["products"] else {
+            panic!("products is not a list");
+        };
+        assert_eq!(products.len(), 2);
+        assert_eq!(products[1]["url"], StringItem(String::from("/desk")));
+    }
+
+    #[test]
+    fn zip_test() {
+        let html = r#"<div class="names"><b>Lamp</b><b>Desk</b></div>
+            <div class="prices"><i>$20</i><i>$90</i><i>$5</i></div>"#;
+        let yaml_config = r#"
+        products:
+            zip:
+                name:
+                    selector: .names b
+                price:
+                    selector: .prices i
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let DataItems(products) = &populate_values(&page(html), &data_config)["products"] else {
+            panic!("products is not a list");
+        };
+        assert_eq!(products.len(), 2);
+        assert_eq!(products[1]["name"], StringItem(String::from("Desk")));
+        assert_eq!(products[1]["price"], StringItem(String::from("$90")));
+    }
+
+    #[test]
+    fn list_pagination_test() {
+        let html =
+            r#"<h1>Lamp</h1><p class="review">Bright</p><a class="next" href="?p=2">Next</a>"#;
+        let yaml_config = r#"
+        reviews:
+            selector: .review
+            pagination:
+                next: a.next
+            data:
+                text:
+                    selector: p
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+        let mut page = page(html);
+        page.frames.insert(
+            String::from("?p=2"),
+            String::from(r#"<p class="review">Dim</p><a class="next" href="?p=3">Next</a>"#),
+        );
+
+        let DataItems(reviews) = &populate_values(&page, &data_config)["reviews"] else {
+            panic!("reviews is not a list");
+        };
+        assert_eq!(reviews.len(), 2);
+        assert_eq!(reviews[1]["text"], StringItem(String::from("Dim")));
+    }
+
+    #[test]
+    fn max_items_test() {
+        let html = r#"<ul><li>Lamp</li><li>Desk</li><li>Chair</li></ul>"#;
+        let yaml_config = r#"
+        products:
+            selector: li
+            maxItems: 2
+            data:
+                name:
+                    selector: li
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let DataItems(products) = &populate_values(&page(html), &data_config)["products"] else {
+            panic!("products is not a list");
+        };
+        assert_eq!(products.len(), 2);
+    }
+
+    #[test]
+    fn script_json_test() {
+        let html = r#"<script id="__NEXT_DATA__" type="application/json">
+            {"props": {"pageProps": {"items": [{"name": "Lamp"}, {"name": "Desk"}]}}}
+        </script>"#;
+        let yaml_config = r#"
+        names:
+            scriptJson:
+                selector: script#__NEXT_DATA__
+                path: $.props.pageProps.items[*].name
+        missing:
+            scriptJson:
+                selector: script#__NUXT__
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let data = populate_values(&page(html), &data_config);
+        assert_eq!(data["names"], JsonItem(serde_json::json!(["Lamp", "Desk"])));
+        assert_eq!(data["missing"], JsonItem(serde_json::Value::Null));
+    }
+
+    #[test]
+    fn regex_test() {
+        let html = r#"<p>SKU: A-1234</p><script>window.price = 19.99;</script>"#;
+        let yaml_config = r#"
+        sku:
+            selector: p
+            regex: "[A-Z]-\\d+"
+        price:
+            source: raw
+            regex: "window.price = ([\\d.]+)"
+        stock:
+            source: raw
+            regex: "stock = (\\d+)"
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let data = populate_values(&page(html), &data_config);
+        assert_eq!(data["sku"], StringItem(String::from("A-1234")));
+        assert_eq!(data["price"], StringItem(String::from("19.99")));
+        assert_eq!(data["stock"], JsonItem(serde_json::Value::Null));
+
+        let invalid = "config: { url: 'https://example.com' }\ndata: { list: { selector: ul, data: { sku: { selector: li, regex: '[' } } } }";
+        assert!(parse_config(invalid).is_err());
+    }
+
+    #[test]
+    fn meta_test() {
+        let html = r#"<html><head>
+            <meta name="description" content="Desk lamps">
+            <meta property="og:title" content="Lamp">
+            </head><body><h1>Lamp</h1></body></html>"#;
+        let yaml_config = r#"
+        description:
+            meta: description
+        title:
+            meta: og:title
+        image:
+            meta: og:image
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        let data = populate_values_within(&Document::parse(&page(html)), &data_config, Some("h1"));
+        assert_eq!(data["description"], StringItem(String::from("Desk lamps")));
+        assert_eq!(data["title"], StringItem(String::from("Lamp")));
+        assert_eq!(data["image"], StringItem(String::new()));
+    }
+
+    #[test]
+    fn link_test() {
+        let html = r#"<html><head>
+            <link rel="canonical" href="/lamps/desk-lamp">
+            <link rel="shortcut icon" href="https://cdn.example.com/favicon.ico">
+            </head><body></body></html>"#;
+        let yaml_config = r#"
+        canonical:
+            link: canonical
+        icon:
+            link: icon
+        alternate:
+            link: alternate
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+        let mut page = page(html);
+        page.final_url = String::from("https://example.com/lamps?page=2");
+
+        let data = populate_values(&page, &data_config);
+        assert_eq!(
+            data["canonical"],
+            StringItem(String::from("https://example.com/lamps/desk-lamp"))
+        );
+        assert_eq!(
+            data["icon"],
+            StringItem(String::from("https://cdn.example.com/favicon.ico"))
+        );
+        assert_eq!(data["alternate"], StringItem(String::new()));
+    }
+
+    #[test]
+    fn diagnostics_test() {
+        let html = r#"<h1>Lamp</h1><p class="price">Call us</p>"#;
+        let yaml_config = r#"
+        title:
+            selector: h1
+        subtitle:
+            selector: h2
+        price:
+            selector: .price
+            regex: "\\d+"
+        stock:
+            selector: h1
+            map:
+                In stock: true
+            mapDefault: false
+        sale:
+            selector: .sale
+            when:
+                exists: .sale
+        "#;
+        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
+
+        assert_eq!(
+            diagnostics(&Document::parse(&page(html)), &data_config, None),
+            serde_json::json!({
+                "title": { "matches": 1 },
+                "subtitle": { "matches": 0 },
+                "price": { "matches": 1, "emptiedBy": "regex" },
+                "stock": { "matches": 1, "default": "mapDefault" },
+                "sale": { "skipped": "when" },
+            })
+        );
+    }
+}
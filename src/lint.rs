@@ -0,0 +1,207 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use schemars::schema_for;
+use serde_json::{Map, Value as JsonValue};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use validator::Validate;
+
+use crate::migrate::upgrade;
+use crate::types::ScrapeRoot;
+
+/// Prints every issue found in the config, fails when there is any
+pub fn lint(input: &Path) -> AnyhowResult<()> {
+    let source = read_to_string(input)
+        .with_context(|| format!("could not read file `{}`", input.display()))?;
+    let issues = lint_source(&source)?;
+
+    for issue in &issues {
+        println!("{}: {}", input.display(), issue);
+    }
+
+    if !issues.is_empty() {
+        bail!("{} issue(s) found", issues.len());
+    }
+
+    Ok(())
+}
+
+pub fn lint_source(source: &str) -> AnyhowResult<Vec<String>> {
+    let mut value = serde_yaml::from_str::<Value>(source).context("invalid YAML")?;
+
+    let mut issues = vec![];
+    match upgrade(&mut value) {
+        Err(error) => return Ok(vec![error.to_string()]),
+        Ok(true) => issues
+            .push("written for an older karkinos, upgrade it with `karkinos migrate`".to_string()),
+        Ok(false) => {}
+    }
+
+    let schema = serde_json::to_value(schema_for!(ScrapeRoot))?;
+    let definitions = schema["definitions"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+
+    check_fields(&value, &schema, &definitions, "", &mut issues);
+
+    match serde_yaml::from_value::<ScrapeRoot>(value) {
+        Err(error) => issues.push(format!("invalid config: {}", error)),
+        Ok(config) => {
+            if let Err(error) = config.validate() {
+                issues.push(format!("invalid config: {}", error));
+            }
+            issues.extend(config.warnings());
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Reports keys the schema doesn't know, these are silently ignored outside
+/// of the root and `output`
+fn check_fields(
+    value: &Value,
+    schema: &JsonValue,
+    definitions: &Map<String, JsonValue>,
+    path: &str,
+    issues: &mut Vec<String>,
+) {
+    let variants = variants(schema, definitions);
+
+    match value {
+        Value::Mapping(mapping) => {
+            let properties = variants
+                .iter()
+                .filter_map(|variant| variant["properties"].as_object())
+                .flatten()
+                .map(|(key, schema)| (key.as_str(), schema))
+                .collect::<BTreeMap<_, _>>();
+            let additional = variants
+                .iter()
+                .map(|variant| &variant["additionalProperties"])
+                .find(|additional| additional.is_object());
+
+            for (key, value) in mapping {
+                let Some(key) = key.as_str() else { continue };
+                let field_path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+
+                match (properties.get(key), additional) {
+                    (Some(&schema), _) | (None, Some(schema)) => {
+                        check_fields(value, schema, definitions, &field_path, issues);
+                    }
+                    (None, None) if properties.is_empty() => {}
+                    (None, None) => match suggestion(key, properties.keys().copied()) {
+                        Some(known) if mapping.contains_key(known) => issues.push(format!(
+                            "`{}` is also set as `{}`, `{}` is ignored",
+                            field_path, known, key
+                        )),
+                        Some(known) => issues.push(format!(
+                            "`{}` is not a known field, did you mean `{}`?",
+                            field_path, known
+                        )),
+                        None => issues.push(format!("`{}` is not a known field", field_path)),
+                    },
+                }
+            }
+        }
+        Value::Sequence(items) => {
+            let Some(schema) = variants
+                .iter()
+                .map(|variant| &variant["items"])
+                .find(|items| items.is_object())
+            else {
+                return;
+            };
+            for (index, item) in items.iter().enumerate() {
+                check_fields(
+                    item,
+                    schema,
+                    definitions,
+                    &format!("{}[{}]", path, index),
+                    issues,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The schema itself and every `$ref`/`anyOf`/`allOf`/`oneOf` it expands to
+fn variants<'a>(
+    schema: &'a JsonValue,
+    definitions: &'a Map<String, JsonValue>,
+) -> Vec<&'a JsonValue> {
+    let schema = match schema["$ref"].as_str() {
+        Some(reference) => reference
+            .strip_prefix("#/definitions/")
+            .and_then(|name| definitions.get(name))
+            .unwrap_or(schema),
+        None => schema,
+    };
+
+    let mut variants = vec![schema];
+    for combinator in ["anyOf", "allOf", "oneOf"] {
+        if let Some(subschemas) = schema[combinator].as_array() {
+            subschemas
+                .iter()
+                .for_each(|subschema| variants.extend(self::variants(subschema, definitions)));
+        }
+    }
+
+    variants
+}
+
+/// A known field spelled the same apart from case and `_`/`-`, which catches
+/// `snake_case` keys written for `camelCase` fields
+fn suggestion<'a>(key: &str, mut known: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let normalize = |key: &str| key.replace(['_', '-'], "").to_lowercase();
+    let key = normalize(key);
+
+    known.find(|known| normalize(known) == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::lint_source;
+
+    #[test]
+    fn lint_test() {
+        let source = "
+config:
+  url: https://example.com
+  retry_delay: 500
+  maxRedirects: 3
+  max_redirects: 5
+  urls:
+    - url: https://example.com/a
+      connect_timeout: 100
+data:
+  items:
+    selector: li
+    nth: 1
+    data:
+      name:
+        selector: .name
+        atr: title
+";
+
+        let issues = lint_source(source).unwrap();
+
+        assert_eq!(
+            issues,
+            vec![
+                "`config.retry_delay` is not a known field, did you mean `retryDelay`?",
+                "`config.max_redirects` is also set as `maxRedirects`, `max_redirects` is ignored",
+                "`config.urls[0].connect_timeout` is not a known field, did you mean `connectTimeout`?",
+                "`data.items.data.name.atr` is not a known field",
+                "`items`: `nth` picks the items of the `data` list, older karkinos ignored it",
+            ]
+        );
+    }
+}
@@ -1,176 +1,742 @@
-use anyhow::{Context, Result as AnyhowResult};
-use clap::Parser;
-use rayon::prelude::*;
-use scraper::Html;
-use std::fs::{read_to_string, File};
-use std::io::stdout;
+use anyhow::{bail, Context, Result as AnyhowResult};
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use log::{info, warn};
+use rand::seq::index;
+use rand::thread_rng;
+use std::env;
+use std::fs::create_dir_all;
+use std::iter;
 use std::path::PathBuf;
-use std::sync::Arc;
-use validator::Validate;
+use std::process::exit;
+use std::time::Instant;
 
-use crate::types::{DataConfig, ItemConfig, ReturnedData, ReturnedDataItem, ScrapeRoot};
-
-mod types;
+use karkinos::antibot::Aborted;
+use karkinos::bench::bench;
+use karkinos::cassette::Cassette;
+use karkinos::checksum::{write_sidecars, Checksum};
+use karkinos::compress::Compression;
+use karkinos::encrypt::{encrypt_secret, Encryption};
+use karkinos::import::{import_curl, import_scrape_it};
+use karkinos::lint::lint;
+use karkinos::migrate::migrate;
+use karkinos::output::{records, run_path, write_output, JsonStyle};
+use karkinos::pipeline::{Output, Pipeline, ScrapeHooks, ScrapeOptions, Scraped};
+use karkinos::plugin::Plugin;
+use karkinos::report::RunReport;
+use karkinos::schema::{write_schema, SchemaFormat};
+use karkinos::stream::Publisher;
+use karkinos::types::{Page, ReturnedPage, ScrapeRoot, StreamOutput};
+use karkinos::upload::is_remote;
+use karkinos::visited::Visited;
+use karkinos::warc::{read_archive, WarcWriter};
+use karkinos::{
+    alerts, daemon, database, fixtures, history, is_empty_result, load_config, notify,
+    parse_config, snapshot,
+};
 
 /// CLI application to scrape website based on yml config 🦀
 /// Inspired by: https://github.com/IonicaBizau/scrape-it ❤️
-#[derive(Parser)]
+#[derive(Parser, Default)]
 #[clap(version, about, verbatim_doc_comment, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Config file location
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Config file location, the `KARKINOS_CONFIG` environment variable holds
+    /// the whole config instead when it's left out
     #[clap(parse(from_os_str), last = false)]
-    input: PathBuf,
+    input: Option<PathBuf>,
 
-    /// Output file location
-    #[clap(parse(from_os_str), short, long)]
+    /// Output file location, `s3://bucket/key` and `gs://bucket/object` upload the output;
+    /// `{config}`, `{date}` and `{time}` are replaced by the config name and the UTC start
+    #[clap(parse(from_os_str), short, long, env = "KARKINOS_OUTPUT")]
     output: Option<PathBuf>,
+
+    /// Write the output's digest next to it, as `<output>.sha256` or `.sha512`
+    #[clap(arg_enum, long)]
+    checksum: Option<Checksum>,
+
+    /// Sign the output with this Ed25519 key (PKCS#8 DER, e.g. from
+    /// `openssl genpkey -algorithm ed25519 -outform DER`) into `<output>.sig`
+    #[clap(parse(from_os_str), long)]
+    sign_key: Option<PathBuf>,
+
+    /// Also write the output to stdout when it goes to `--output`, as plain
+    /// JSON even when the file is compressed or encrypted
+    #[clap(long)]
+    tee: bool,
+
+    /// Compress the output, `.gz` and `.zst` output files are compressed without it
+    #[clap(arg_enum, long)]
+    compress: Option<Compression>,
+
+    /// Encrypt the output as it is written, to `age:<recipient>` (an `age1...` public key)
+    #[clap(long)]
+    encrypt: Option<Encryption>,
+
+    /// Indent the output JSON or not, stdout is pretty and files compact by default
+    #[clap(arg_enum, long)]
+    json_style: Option<JsonStyle>,
+
+    /// Write a machine-readable run report (JSON) to this file
+    #[clap(parse(from_os_str), long)]
+    report: Option<PathBuf>,
+
+    /// Exit with a non-zero code when every page produced empty results
+    #[clap(long)]
+    fail_on_empty: bool,
+
+    /// Archive every fetched response into this WARC file (gzipped for `.gz`)
+    #[clap(parse(from_os_str), long)]
+    warc: Option<PathBuf>,
+
+    /// Extract from the responses archived in this WARC file instead of fetching
+    #[clap(parse(from_os_str), long, conflicts_with = "warc")]
+    from_warc: Option<PathBuf>,
+
+    /// Record every HTTP request and its response into this cassette file
+    #[clap(parse(from_os_str), long, conflicts_with_all = &["replay", "from-warc"])]
+    record: Option<PathBuf>,
+
+    /// Answer HTTP requests from this cassette file instead of the network
+    #[clap(parse(from_os_str), long, conflicts_with = "from-warc")]
+    replay: Option<PathBuf>,
+
+    /// Add a `_meta` object (url, final url after redirects, status, run id) to every page
+    #[clap(long)]
+    meta: bool,
+
+    /// Try the config on the first N `urls` and N items of each list: at most N
+    /// records are printed, and `visited`, `history`, `notify`, database, stream
+    /// and sink outputs are skipped
+    #[clap(long)]
+    sample: Option<usize>,
+
+    /// With `--sample`, pick the urls at random
+    #[clap(long, requires = "sample")]
+    sample_random: bool,
+
+    /// Add a `_diagnostics` object to every page: per field, whether `when` skipped
+    /// it, how many elements its selectors matched, which transform emptied it and
+    /// whether `mapDefault` was used
+    #[clap(long)]
+    explain: bool,
+
+    /// Threads used for extraction and simultaneous fetches, overrides `concurrency`
+    #[clap(short, long)]
+    jobs: Option<usize>,
+
+    /// Run identifier for `_meta` and the report, replaces `{runId}` in output,
+    /// report and WARC paths; a UTC timestamp by default
+    #[clap(long)]
+    run_id: Option<String>,
+
+    /// Write the output and report of each run to `<dir>/<run id>/`, unless set explicitly
+    #[clap(parse(from_os_str), long)]
+    runs_dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert configs of other scrapers into karkinos configs
+    #[clap(subcommand)]
+    Import(ImportCommand),
+    /// Query the records stored by the config's `history`
+    #[clap(subcommand)]
+    History(HistoryCommand),
+    /// Time the extraction of every field, repeated over the same pages
+    Bench {
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        #[clap(long, default_value = "10")]
+        iterations: u32,
+
+        /// Saved page to extract from instead of fetching the config urls
+        #[clap(parse(from_os_str), long, conflicts_with = "from-warc")]
+        html: Option<PathBuf>,
+
+        /// Extract from the responses archived in this WARC file instead of fetching
+        #[clap(parse(from_os_str), long)]
+        from_warc: Option<PathBuf>,
+    },
+    /// Extract the HTML files of a fixtures directory and compare the records
+    /// with a golden JSON file
+    Test {
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Directory of saved pages, `<config>.fixtures` by default
+        #[clap(parse(from_os_str), long)]
+        fixtures: Option<PathBuf>,
+
+        /// Expected records by fixture file name, `<config>.golden.json` by default
+        #[clap(parse(from_os_str), long)]
+        golden: Option<PathBuf>,
+
+        /// Write the records as the new golden file instead of comparing
+        #[clap(long)]
+        update: bool,
+    },
+    /// Serve a directory of saved pages over HTTP to develop configs against;
+    /// `?_status=503` and `?_delay=500` change the answer to one request
+    ServeFixtures {
+        #[clap(parse(from_os_str))]
+        dir: PathBuf,
+
+        #[clap(long, default_value = "8000")]
+        port: u16,
+
+        /// Delay before answering every request, in milliseconds
+        #[clap(long, default_value = "0")]
+        latency: u64,
+    },
+    /// Run the job files put into a directory, each a config (inline or a
+    /// path) with optional `urls`; results and acks go to `done/` and `failed/`
+    Daemon {
+        #[clap(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Jobs running at the same time
+        #[clap(long, default_value = "2")]
+        workers: usize,
+
+        /// Delay between checks for new jobs, in milliseconds
+        #[clap(long, default_value = "1000")]
+        poll: u64,
+    },
+    /// Check a config for mistakes that validation lets through
+    Lint {
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Upgrade a config written for an older karkinos to the current format,
+    /// reporting what changed
+    Migrate {
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output file location, stdout by default; comments are not kept
+        #[clap(parse(from_os_str), short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write the JSON Schema of the config file
+    Schema {
+        /// Schema file location, stdout by default
+        #[clap(parse(from_os_str), long)]
+        out: Option<PathBuf>,
+
+        #[clap(arg_enum, long, default_value = "json")]
+        format: SchemaFormat,
+    },
+    /// Encrypt a header value, password or token for the config, printed as
+    /// `ENC[age:...]`; runs decrypt it with `KARKINOS_AGE_IDENTITY` or
+    /// `KARKINOS_AGE_IDENTITY_FILE`
+    Secret {
+        value: String,
+
+        /// `age:<recipient>`, the public key of the identity runs decrypt with
+        #[clap(long)]
+        to: Encryption,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Print every stored version of the records
+    Show {
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Only the record with this key
+        #[clap(long)]
+        id: Option<String>,
+    },
+    /// Print the fields that changed between consecutive runs
+    Diff {
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Only the record with this key
+        #[clap(long)]
+        id: Option<String>,
+
+        /// Only changes of this field
+        #[clap(long)]
+        field: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImportCommand {
+    /// Convert scrape-it options (JSON or a `scrapeIt(url, {...})` JS file)
+    ScrapeIt {
+        #[clap(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Url to put in the generated config
+        #[clap(long)]
+        url: Option<String>,
+
+        /// Output file location, stdout by default
+        #[clap(parse(from_os_str), short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert a "copy as cURL" command from browser devtools
+    Curl {
+        /// The whole curl command, quoted
+        command: String,
+
+        /// Output file location, stdout by default
+        #[clap(parse(from_os_str), short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 fn main() -> AnyhowResult<()> {
     // initialize
     env_logger::init();
 
-    let args = Cli::parse();
+    let mut args = Cli::parse();
 
-    let config_file_path = args.input.clone().into_os_string().into_string().unwrap();
+    if let Some(command) = &args.command {
+        return match command {
+            Command::Import(ImportCommand::ScrapeIt { input, url, output }) => {
+                import_scrape_it(input, url.as_deref(), output.as_deref())
+            }
+            Command::Import(ImportCommand::Curl { command, output }) => {
+                import_curl(command, output.as_deref())
+            }
+            Command::History(HistoryCommand::Show { input, id }) => {
+                history::show(input, id.as_deref())
+            }
+            Command::History(HistoryCommand::Diff { input, id, field }) => {
+                history::diff(input, id.as_deref(), field.as_deref())
+            }
+            Command::Bench {
+                input,
+                iterations,
+                html,
+                from_warc,
+            } => bench(input, *iterations, html.as_deref(), from_warc.as_deref()),
+            Command::ServeFixtures { dir, port, latency } => fixtures::serve(dir, *port, *latency),
+            Command::Daemon { dir, workers, poll } => {
+                daemon::watch(dir, *workers, *poll, |config, name, paths| {
+                    let args = Cli {
+                        output: Some(paths.output.clone()),
+                        ..Default::default()
+                    };
+                    let mut report = RunReport {
+                        run_id: name.to_string(),
+                        ..Default::default()
+                    };
 
-    // Read file from args
-    let config_file = read_to_string(args.input)
-        .with_context(|| format!("could not read file `{}`", config_file_path))?;
+                    let started = Instant::now();
+                    let result = run(&args, config, name, &mut report);
+                    report.finish(started, result.is_ok());
+                    report.write(&paths.report)?;
 
-    let config_serialized = serde_yaml::from_str::<ScrapeRoot>(config_file.as_str())
-        .with_context(|| format!("invalid config"))?;
+                    result.map(|_| ())
+                })
+            }
+            Command::Lint { input } => lint(input),
+            Command::Migrate { input, output } => migrate(input, output.as_deref()),
+            Command::Test {
+                input,
+                fixtures,
+                golden,
+                update,
+            } => snapshot::test(input, fixtures.as_deref(), golden.as_deref(), *update),
+            Command::Schema { out, format } => write_schema(out.as_deref(), *format),
+            Command::Secret { value, to } => {
+                println!("{}", encrypt_secret(value, to)?);
+                Ok(())
+            }
+        };
+    }
 
-    config_serialized
-        .validate()
-        .with_context(|| format!("invalid config"))?;
+    let started = Instant::now();
+    let started_at = Utc::now();
+    let run_id = args
+        .run_id
+        .clone()
+        .unwrap_or_else(|| started_at.format("%Y%m%dT%H%M%SZ").to_string());
 
-    let url = config_serialized.config.url;
+    if let Some(runs_dir) = &args.runs_dir {
+        let run_dir = runs_dir.join(&run_id);
+        create_dir_all(&run_dir)
+            .with_context(|| format!("can't create run directory `{}`", run_dir.display()))?;
 
-    let html = reqwest::blocking::get(url)?
-        .text()
-        .with_context(|| format!("can't get url content"))?;
+        args.output.get_or_insert(run_dir.join("output.json"));
+        args.report.get_or_insert(run_dir.join("report.json"));
+    }
+    let config_name = args
+        .input
+        .as_ref()
+        .and_then(|input| input.file_stem())
+        .map_or("config".into(), |name| name.to_string_lossy());
+    let config_name = config_name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    for path in [&mut args.output, &mut args.report, &mut args.warc] {
+        *path = path
+            .take()
+            .map(|path| run_path(&path, &run_id, &config_name, started_at));
+    }
 
-    let data = populate_values(html, config_serialized.data.clone());
+    let mut report = RunReport {
+        run_id: run_id.clone(),
+        ..Default::default()
+    };
 
-    match args.output {
-        None => {
-            serde_json::to_writer_pretty(stdout(), &data)
-                .with_context(|| format!("can't write output"))?;
+    let config = match &args.input {
+        Some(input) => load_config(input),
+        None => config_from_env(),
+    };
+    let config = match args.sample {
+        Some(size) => {
+            if args.output.take().is_some() {
+                warn!("`--output` is ignored with `--sample`, the records are printed");
+            }
+            config.map(|config| sample(config, size, args.sample_random))
         }
-        Some(output) => {
-            let dest = File::create(output).unwrap();
-            serde_json::to_writer_pretty(dest, &data)
-                .with_context(|| format!("can't write output"))?;
+        None => config,
+    };
+    let result = config.and_then(|config| run(&args, config, &run_id, &mut report));
+
+    if let Some(report_path) = &args.report {
+        report.finish(started, result.is_ok());
+        report.write(report_path)?;
+    }
+
+    if let Err(error) = &result {
+        // already logged with the page errors
+        if let Some(aborted) = error.downcast_ref::<Aborted>() {
+            exit(aborted.exit_code);
         }
     }
+    if let Some(exit_code) = result? {
+        exit(exit_code);
+    }
 
     Ok(())
 }
 
-fn populate_values(html: String, config: DataConfig) -> ReturnedData {
-    let html = Arc::new(html.clone());
+/// Returns the exit code of matched `alerts`
+fn run(
+    args: &Cli,
+    config_serialized: ScrapeRoot,
+    run_id: &str,
+    report: &mut RunReport,
+) -> AnyhowResult<Option<i32>> {
+    let started_at = Utc::now();
+    report.warnings = config_serialized.warnings();
+    report
+        .warnings
+        .iter()
+        .for_each(|warning| warn!("{}", warning));
+
+    let pipeline = Pipeline::new(&config_serialized)?;
+    let mut fetcher = pipeline.fetcher()?;
+    let cassette = match (&args.record, &args.replay) {
+        (Some(path), _) => Some(Cassette::record(path)),
+        (None, Some(path)) => Some(Cassette::replay(path)?),
+        (None, None) => None,
+    };
+    if let Some(cassette) = cassette {
+        if config_serialized.config.browser.is_some() {
+            warn!("cassettes only hold HTTP requests, browser renders are not recorded");
+        }
+        fetcher = fetcher.with_cassette(cassette);
+    }
+    let mut targets = config_serialized.config.targets();
+
+    let visited = match &config_serialized.config.visited {
+        Some(config) => Some(Visited::open(config)?),
+        None => None,
+    };
+    if let Some(visited) = &visited {
+        let listed = targets.len();
+        let mut unvisited = vec![];
+        for target in targets {
+            if !visited.is_fresh(&target.url)? {
+                unvisited.push(target);
+            }
+        }
+        targets = unvisited;
+
+        info!("skipping {} already visited urls", listed - targets.len());
+        if targets.is_empty() {
+            warn!("every url was already visited, nothing to scrape");
+            return Ok(None);
+        }
+    }
+
+    let archive = match &args.from_warc {
+        Some(path) => Some(read_archive(path)?),
+        None => None,
+    };
+    let mut hooks = RunHooks {
+        warc: match &args.warc {
+            Some(path) => Some(WarcWriter::create(path)?),
+            None => None,
+        },
+        publisher: match &config_serialized.output.stream {
+            Some(stream) => Some((Publisher::connect(stream)?, stream)),
+            None => None,
+        },
+        visited: visited.as_ref(),
+    };
+
+    let options = ScrapeOptions {
+        archive: archive.as_ref(),
+        jobs: args.jobs,
+        meta: args.meta.then_some(run_id),
+        explain: args.explain,
+    };
+    let mut scraped = pipeline.scrape(&fetcher, &targets, &options, &mut hooks)?;
+    report.urls.append(&mut scraped.reports);
+    fetcher.save_cassette()?;
+
+    if let Some((publisher, _)) = &mut hooks.publisher {
+        publisher.flush()?;
+    }
+
+    report.blocked = report
+        .urls
+        .iter()
+        .filter(|url| url.blocked.is_some())
+        .count();
+    let errors = &mut scraped.errors;
+    if let Some(index) = errors.iter().position(|error| error.is::<Aborted>()) {
+        return Err(errors.remove(index));
+    }
+
+    if scraped.pages.is_empty() && !errors.is_empty() {
+        return Err(errors.remove(0));
+    }
+
+    let Output {
+        document,
+        pages: written,
+        joined,
+    } = pipeline.output(&scraped, started_at)?;
+    // sinks get what the output file does: `join`'s records, or those of the pages
+    let sink_records = |field: Option<&String>| match &joined {
+        Some(joined) => Ok(joined.iter().collect()),
+        None => records(field, &written),
+    };
+    let Scraped { pages, errors, .. } = scraped;
+    let output = &config_serialized.output;
+    write_output(
+        args.output.as_deref(),
+        args.compress,
+        args.encrypt.as_ref(),
+        args.json_style,
+        &document,
+    )?;
+    if args.checksum.is_some() || args.sign_key.is_some() {
+        match args.output.as_deref() {
+            Some(output) if !is_remote(&output.to_string_lossy()) => {
+                write_sidecars(output, args.checksum, args.sign_key.as_deref())?
+            }
+            _ => warn!("`--checksum` and `--sign-key` need a local `--output` file"),
+        }
+    }
+    if args.tee && args.output.is_some() {
+        write_output(None, None, None, args.json_style, &document)?;
+    }
+
+    if let Some(database) = &config_serialized.output.database {
+        database::insert(database, &sink_records(database.records.as_ref())?)?;
+    }
+
+    let plugins = config_serialized.plugins.as_ref();
+    for path in plugins.map_or(&[][..], |plugins| &plugins.sinks) {
+        Plugin::load(path)?.sink(&sink_records(output.records.as_ref())?)?;
+    }
+
+    if let Some(history) = &config_serialized.history {
+        history::store(history, run_id, &pages)?;
+    }
+
+    let alerts = match &config_serialized.alerts {
+        Some(alerts) => alerts::check(alerts, &pages)?,
+        None => vec![],
+    };
+    report.alerts = alerts.iter().map(|alert| alert.message.clone()).collect();
+    let alerted = !alerts.is_empty();
+
+    if let Some(notify) = &config_serialized.notify {
+        notify::notify(notify, &pages, errors.len(), alerts)?;
+    }
+
+    if !errors.is_empty() {
+        bail!("{} of {} pages failed", errors.len(), targets.len());
+    }
+    if args.fail_on_empty && pages.iter().all(|page| is_empty_result(&page.data)) {
+        bail!("scrape produced empty results");
+    }
+
+    let alerts = config_serialized.alerts.as_ref();
+    Ok(alerts
+        .and_then(|alerts| alerts.exit_code)
+        .filter(|_| alerted))
+}
+
+/// `config` limited to `size` of its `urls`, list items and records, without
+/// the parts that keep state or reach other systems
+fn sample(mut config: ScrapeRoot, size: usize, random: bool) -> ScrapeRoot {
+    let urls = &mut config.config.urls;
+    if random {
+        let mut picked =
+            index::sample(&mut thread_rng(), urls.len(), size.min(urls.len())).into_vec();
+        picked.sort_unstable();
+        *urls = picked
+            .into_iter()
+            .map(|index| urls[index].clone())
+            .collect();
+    } else {
+        urls.truncate(size);
+    }
+
+    let max_records = &mut config.config.max_records;
+    *max_records = Some(max_records.map_or(size, |max_records| max_records.min(size)));
+    // a single `url` or a `root` list gets its records from lists, paginated ones included
+    let templates = config
+        .templates
+        .values_mut()
+        .map(|template| &mut template.data);
+    for data in iter::once(&mut config.data).chain(templates) {
+        for field in data.values_mut() {
+            if field.data.is_some() || field.zip.is_some() {
+                let max_items = field
+                    .max_items
+                    .map_or(size, |max_items| max_items.min(size));
+                field.max_items = Some(max_items);
+            }
+        }
+    }
+    config.config.visited = None;
+    config.history = None;
+    config.notify = None;
+    config.output.database = None;
+    config.output.stream = None;
+    if let Some(plugins) = &mut config.plugins {
+        plugins.sinks.clear();
+    }
 
     config
-        .into_par_iter()
-        .map(|(name, config)| {
-            let html_parsed = Html::parse_fragment(html.as_str());
-            let selector = config.get_item_selector();
-            let selected_element = html_parsed.root_element().select(&selector);
-
-            match config {
-                ItemConfig {
-                    data: Some(inner), ..
-                } => {
-                    let inner_htmls = selected_element
-                        .map(|elem| elem.html())
-                        .collect::<Vec<String>>();
-                    let inner_values = inner_htmls
-                        .into_par_iter()
-                        .map(|elem| populate_values(elem, inner.clone()))
-                        .collect::<Vec<ReturnedData>>();
-
-                    ReturnedData::from([(name, ReturnedDataItem::DataItems(inner_values))])
-                }
-                _ => {
-                    let selected_element_nth = selected_element.clone().nth(config.nth);
-                    let value = match selected_element_nth {
-                        None => String::from(""),
-                        Some(selected_element) => match config.attr {
-                            None => selected_element.inner_html(),
-                            Some(attr) => selected_element
-                                .value()
-                                .attr(&attr)
-                                .unwrap_or("")
-                                .to_string(),
-                        },
-                    };
+}
 
-                    ReturnedData::from([(name, ReturnedDataItem::StringItem(value))])
-                }
+/// The whole config in `KARKINOS_CONFIG`, for containers run without files
+fn config_from_env() -> AnyhowResult<ScrapeRoot> {
+    let Ok(config) = env::var("KARKINOS_CONFIG") else {
+        bail!("no config file given and `KARKINOS_CONFIG` is not set");
+    };
+
+    parse_config(&config)
+}
+
+/// Writes fetched pages to `--warc`, streams the records and marks the urls
+/// visited as the run goes
+struct RunHooks<'a> {
+    warc: Option<WarcWriter>,
+    publisher: Option<(Publisher, &'a StreamOutput)>,
+    visited: Option<&'a Visited>,
+}
+
+impl ScrapeHooks for RunHooks<'_> {
+    fn fetched(&mut self, url: &str, page: &Page) -> AnyhowResult<()> {
+        if let Some(warc) = &mut self.warc {
+            warc.write_response(url, page)?;
+        }
+
+        Ok(())
+    }
+
+    fn scraped(&mut self, url: &str, pages: &[ReturnedPage]) -> AnyhowResult<()> {
+        if let Some((publisher, stream)) = &mut self.publisher {
+            for record in records(stream.records.as_ref(), pages)? {
+                publisher.publish(record)?;
             }
-        })
-        .reduce(
-            || ReturnedData::new(),
-            |dt1, dt2| {
-                dt2.iter().fold(dt1, |mut acc, (name, value)| {
-                    acc.entry(name.clone()).or_insert(value.clone());
-
-                    acc
-                })
-            },
-        )
+        }
+        // recorded right away, so an interrupted run resumes after it
+        if let Some(visited) = self.visited {
+            visited.mark(url)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ReturnedDataItem::{DataItems, StringItem};
-    use crate::{populate_values, DataConfig};
+    use super::*;
+    use karkinos::types::UrlConfig;
 
     #[test]
-    fn populate_values_test() {
-        // From w3schools
-        let html = r#"
-            <!DOCTYPE html>
-            <html>
-               <body>
-                  <h1>The article element</h1>
-                  <article>
-                     <h2>Google Chrome</h2>
-                     <p>Google Chrome is a web browser developed by Google, released in 2008. Chrome is the world's most popular web browser today!</p>
-                  </article>
-                  <article>
-                     <h2>Mozilla Firefox</h2>
-                     <p>Mozilla Firefox is an open-source web browser developed by Mozilla. Firefox has been the second most popular web browser since January, 2018.</p>
-                  </article>
-                  <article>
-                     <h2>Microsoft Edge</h2>
-                     <p>Microsoft Edge is a web browser developed by Microsoft, released in 2015. Microsoft Edge replaced Internet Explorer.</p>
-                  </article>
-               </body>
-            </html>
-        "#.to_string();
-
-        let yaml_config = r#"
-        title:
-            selector: h1
-        articles:
-            selector: article
-            data:
-                title:
-                    selector: h2
-                description: 
-                    selector: p
-        "#;
-        let data_config = serde_yaml::from_str::<DataConfig>(yaml_config).unwrap();
-
-        let data = populate_values(html, data_config);
+    fn sample_test() {
+        let config = parse_config(
+            r#"
+config:
+  urls: ['https://example.com/1', 'https://example.com/2', 'https://example.com/3']
+  maxRecords: 1
+  visited: { path: visited.db }
+data:
+  title: { selector: h1 }
+  reviews: { selector: .review, data: { text: { selector: p } }, maxItems: 1 }
+  products:
+    selector: li
+    data: { name: { selector: a } }
+    pagination: { next: a.next }
+templates:
+  listing: { url: /list, data: { items: { selector: li, data: { name: { selector: a } } } } }
+output:
+  database: { url: 'postgres://localhost/shop', table: products }
+"#,
+        )
+        .unwrap();
 
+        let sampled = sample(config, 2, false);
+        let urls = sampled.config.urls.iter().map(|url| match url {
+            UrlConfig::Url(url) => url.as_str(),
+            UrlConfig::WithOptions(options) => options.url.as_str(),
+        });
         assert_eq!(
-            data.get("title").unwrap().clone(),
-            StringItem(String::from("The article element"))
+            urls.collect::<Vec<_>>(),
+            ["https://example.com/1", "https://example.com/2"]
         );
-        let articles = data.get("articles").unwrap().clone();
-        assert!(matches!(articles, DataItems { .. }));
+        assert_eq!(sampled.config.max_records, Some(1));
+        assert_eq!(sampled.data["title"].max_items, None);
+        assert_eq!(sampled.data["reviews"].max_items, Some(1));
+        assert_eq!(sampled.data["products"].max_items, Some(2));
+        assert_eq!(
+            sampled.templates["listing"].data["items"].max_items,
+            Some(2)
+        );
+        assert!(sampled.config.visited.is_none() && sampled.output.database.is_none());
+    }
+
+    #[test]
+    fn config_from_env_test() {
+        env::remove_var("KARKINOS_CONFIG");
+        assert!(config_from_env().is_err());
+
+        env::set_var(
+            "KARKINOS_CONFIG",
+            "config: { url: 'https://example.com' }\ndata: { title: { selector: h1 } }",
+        );
+        let config = config_from_env().unwrap();
+        assert_eq!(config.config.url.as_deref(), Some("https://example.com"));
+        assert!(config.data.contains_key("title"));
+
+        env::set_var("KARKINOS_CONFIG", "config: {}");
+        assert!(config_from_env().is_err());
+        env::remove_var("KARKINOS_CONFIG");
     }
 }
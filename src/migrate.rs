@@ -0,0 +1,223 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use serde_yaml::{Mapping, Value};
+use std::fs::{read_to_string, File};
+use std::io::{stdout, Write};
+use std::path::Path;
+
+use crate::types::{ScrapeRoot, CONFIG_VERSION};
+
+/// A rewrite of configs from an older format, reporting each change it makes
+type Migration = fn(&mut Value, &mut Vec<String>);
+
+/// The migrations upgrading configs of each version, oldest first
+const MIGRATIONS: [(u32, Migration); 1] = [(1, list_item)];
+
+/// Writes `input` upgraded to the current config format to `output` (stdout
+/// by default) and reports what changed. Comments are not kept
+pub fn migrate(input: &Path, output: Option<&Path>) -> AnyhowResult<()> {
+    let source = read_to_string(input)
+        .with_context(|| format!("could not read file `{}`", input.display()))?;
+    let (config, changes) = migrate_source(&source)?;
+
+    for change in &changes {
+        eprintln!("{}: {}", input.display(), change);
+    }
+    if changes.is_empty() {
+        eprintln!("{}: already in the current format", input.display());
+    }
+    if let Err(error) = serde_yaml::from_value::<ScrapeRoot>(config.clone()) {
+        eprintln!("{}: still not a valid config: {}", input.display(), error);
+    }
+
+    let yaml = serde_yaml::to_string(&config).context("can't write config")?;
+    match output {
+        None => stdout().write_all(yaml.as_bytes())?,
+        Some(output) => File::create(output)
+            .with_context(|| format!("can't create `{}`", output.display()))?
+            .write_all(yaml.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+pub fn migrate_source(source: &str) -> AnyhowResult<(Value, Vec<String>)> {
+    let mut config = serde_yaml::from_str::<Value>(source).context("invalid YAML")?;
+
+    let version = version(&config)?;
+    let mut changes = apply(&mut config, version);
+    if version != Some(CONFIG_VERSION) {
+        if let Some(root) = config.as_mapping_mut() {
+            root.shift_remove("version");
+            let mut versioned = Mapping::new();
+            versioned.insert("version".into(), CONFIG_VERSION.into());
+            versioned.extend(std::mem::take(root));
+            *root = versioned;
+            changes.push(format!("`version` set to {}", CONFIG_VERSION));
+        }
+    }
+
+    Ok((config, changes))
+}
+
+/// Upgrades a config written for an older version in place, refusing ones
+/// newer than this karkinos reads. Configs without `version` are upgraded
+/// only when they use an older format. Whether it was an older one
+pub fn upgrade(config: &mut Value) -> AnyhowResult<bool> {
+    let version = version(config)?;
+    let changes = apply(config, version);
+
+    Ok(version.is_some_and(|version| version < CONFIG_VERSION) || !changes.is_empty())
+}
+
+fn version(config: &Value) -> AnyhowResult<Option<u32>> {
+    let Some(version) = config.get("version") else {
+        return Ok(None);
+    };
+    let Some(version) = version
+        .as_u64()
+        .and_then(|version| u32::try_from(version).ok())
+    else {
+        bail!("`version` must be a whole number");
+    };
+    if version > CONFIG_VERSION {
+        bail!(
+            "config version {} is newer than version {} this karkinos reads, upgrade karkinos (`cargo install karkinos`) to run it",
+            version,
+            CONFIG_VERSION
+        );
+    }
+
+    Ok(Some(version))
+}
+
+/// Runs the migrations of `version` and later ones, all of them without a version
+fn apply(config: &mut Value, version: Option<u32>) -> Vec<String> {
+    let mut changes = vec![];
+    for (from, migration) in MIGRATIONS {
+        if version.is_none_or(|version| version <= from) {
+            migration(config, &mut changes);
+        }
+    }
+
+    changes
+}
+
+/// `listItem` selected the items of a list within `selector`, now `selector`
+/// selects them itself
+fn list_item(config: &mut Value, changes: &mut Vec<String>) {
+    for_each_field(config, &mut |path, field| {
+        let Some(list_item) = field.remove("listItem") else {
+            return;
+        };
+        let list_item = list_item.as_str().unwrap_or_default();
+        let selector = match field.get("selector").and_then(Value::as_str) {
+            Some(selector) if !selector.trim().is_empty() => format!("{} {}", selector, list_item),
+            _ => list_item.to_string(),
+        };
+
+        changes.push(format!(
+            "`{}`: `listItem` merged into `selector: {}`",
+            path, selector
+        ));
+        field.insert("selector".into(), selector.into());
+    });
+}
+
+/// Calls `f` with every field of the root and template `data`, nested ones included
+fn for_each_field(config: &mut Value, f: &mut impl FnMut(&str, &mut Mapping)) {
+    fn visit(data: &mut Value, prefix: &str, f: &mut impl FnMut(&str, &mut Mapping)) {
+        let Some(data) = data.as_mapping_mut() else {
+            return;
+        };
+        for (name, field) in data.iter_mut() {
+            let Some(field) = field.as_mapping_mut() else {
+                continue;
+            };
+            let path = format!("{}{}", prefix, name.as_str().unwrap_or_default());
+
+            f(&path, field);
+            for inner in ["data", "zip"] {
+                if let Some(inner) = field.get_mut(inner) {
+                    visit(inner, &format!("{}.", path), f);
+                }
+            }
+        }
+    }
+
+    if let Some(data) = config.get_mut("data") {
+        visit(data, "", f);
+    }
+    if let Some(templates) = config.get_mut("templates").and_then(Value::as_mapping_mut) {
+        for (name, template) in templates.iter_mut() {
+            if let Some(data) = template.get_mut("data") {
+                let prefix = format!("templates.{}.data.", name.as_str().unwrap_or_default());
+                visit(data, &prefix, f);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_config;
+
+    #[test]
+    fn migrate_test() {
+        let source = r#"
+config:
+  url: https://example.com
+data:
+  title:
+    selector: h1
+  posts:
+    selector: .popular-posts
+    listItem: .post
+    data:
+      tags:
+        listItem: .tag
+        data:
+          name:
+            selector: a
+"#;
+        let (config, changes) = migrate_source(source).unwrap();
+
+        assert_eq!(
+            changes,
+            vec![
+                "`posts`: `listItem` merged into `selector: .popular-posts .post`",
+                "`posts.tags`: `listItem` merged into `selector: .tag`",
+                "`version` set to 2",
+            ]
+        );
+        assert_eq!(config["data"]["posts"]["selector"], ".popular-posts .post");
+        assert!(serde_yaml::from_value::<ScrapeRoot>(config).is_ok());
+        assert!(
+            migrate_source("version: 2\nconfig: { url: 'https://example.com' }")
+                .unwrap()
+                .1
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn version_test() {
+        let config = |version| {
+            format!("{}config: {{ url: 'https://example.com' }}\ndata: {{ posts: {{ selector: ul, listItem: li }} }}", version)
+        };
+
+        assert_eq!(
+            parse_config(&config("version: 1\n")).unwrap().data["posts"].selector,
+            "ul li"
+        );
+        assert!(parse_config(&config("")).is_ok());
+        assert_eq!(
+            parse_config(&config("version: 2\n")).unwrap().data["posts"].selector,
+            "ul"
+        );
+        assert!(
+            format!("{:#}", parse_config(&config("version: 3\n")).unwrap_err())
+                .contains("upgrade karkinos")
+        );
+    }
+}
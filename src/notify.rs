@@ -0,0 +1,255 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use lettre::message::Mailbox;
+use lettre::{Message, SmtpTransport, Transport};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde_json::json;
+use std::fs::{read_to_string, File};
+
+use crate::alerts::Alert;
+use crate::env::interpolate;
+use crate::output::{records, render};
+use crate::types::{
+    EmailNotify, NotifyConfig, NotifyOn, NotifyPer, ReturnedData, ReturnedPage, WebhookKind,
+    WebhookNotify,
+};
+
+/// new records listed in a notification before it is cut off
+const MAX_LISTED_RECORDS: usize = 20;
+
+/// What this run produced compared to the previous one
+pub struct RunSummary<'a> {
+    pub pages: usize,
+    pub failed: usize,
+    pub records: Vec<&'a ReturnedData>,
+    pub new_records: Vec<&'a ReturnedData>,
+    /// records were added or removed since the previous run
+    pub changed: bool,
+    pub alerts: Vec<Alert<'a>>,
+}
+
+/// Sends the configured notifications, comparing against (and updating) `state`
+pub fn notify<'a>(
+    config: &NotifyConfig,
+    pages: &'a [ReturnedPage],
+    failed: usize,
+    alerts: Vec<Alert<'a>>,
+) -> AnyhowResult<()> {
+    let records = records(config.records.as_ref(), pages)?;
+    let previous = match &config.state {
+        Some(state) => read_state(state),
+        None => vec![],
+    };
+
+    let new_records = records
+        .iter()
+        .filter(|record| !previous.contains(record))
+        .copied()
+        .collect::<Vec<_>>();
+    let removed = previous.iter().any(|record| !records.contains(&record));
+
+    let summary = RunSummary {
+        pages: pages.len(),
+        failed,
+        changed: !new_records.is_empty() || removed,
+        new_records,
+        records,
+        alerts,
+    };
+
+    if let Some(email) = &config.email {
+        if should_send(&email.on, &summary) {
+            send_email(email, &summary)?;
+        } else {
+            info!("no changes, skipping email notification");
+        }
+    }
+
+    for webhook in &config.webhooks {
+        if should_send(&webhook.on, &summary) {
+            send_webhook(webhook, &summary)?;
+        }
+    }
+
+    if let Some(state) = &config.state {
+        let file =
+            File::create(state).with_context(|| format!("can't write notify state `{}`", state))?;
+        serde_json::to_writer(file, &summary.records).context("can't write notify state")?;
+    }
+
+    Ok(())
+}
+
+pub fn should_send(on: &NotifyOn, summary: &RunSummary) -> bool {
+    match on {
+        NotifyOn::Always => true,
+        NotifyOn::Changes => summary.changed,
+        NotifyOn::NewRecords => !summary.new_records.is_empty(),
+        NotifyOn::Alerts => !summary.alerts.is_empty(),
+    }
+}
+
+fn read_state(path: &str) -> Vec<ReturnedData> {
+    match read_to_string(path) {
+        Ok(state) => serde_json::from_str(&state).unwrap_or_else(|error| {
+            warn!("ignoring unreadable notify state `{}`: {}", path, error);
+            vec![]
+        }),
+        Err(_) => vec![],
+    }
+}
+
+fn summary_text(summary: &RunSummary) -> String {
+    let mut text = format!(
+        "Scraped {} pages ({} failed): {} records, {} new.\n",
+        summary.pages,
+        summary.failed,
+        summary.records.len(),
+        summary.new_records.len()
+    );
+
+    for alert in &summary.alerts {
+        text.push_str(&format!("\nAlert: {}", alert.message));
+    }
+    if !summary.alerts.is_empty() {
+        text.push('\n');
+    }
+
+    for record in summary.new_records.iter().take(MAX_LISTED_RECORDS) {
+        text.push_str(&format!(
+            "\n{}",
+            serde_json::to_string(record).unwrap_or_default()
+        ));
+    }
+    if summary.new_records.len() > MAX_LISTED_RECORDS {
+        text.push_str(&format!(
+            "\n... and {} more",
+            summary.new_records.len() - MAX_LISTED_RECORDS
+        ));
+    }
+
+    text
+}
+
+fn send_webhook(config: &WebhookNotify, summary: &RunSummary) -> AnyhowResult<()> {
+    let messages = match config.per {
+        NotifyPer::Run => vec![match &config.template {
+            Some(template) => render_summary(template, summary),
+            None => summary_text(summary),
+        }],
+        NotifyPer::Record if config.on == NotifyOn::Alerts => summary
+            .alerts
+            .iter()
+            .map(|alert| match &config.template {
+                Some(template) => render(template, alert.record),
+                None => alert.message.clone(),
+            })
+            .collect(),
+        NotifyPer::Record => summary
+            .new_records
+            .iter()
+            .map(|record| match &config.template {
+                Some(template) => render(template, record),
+                None => serde_json::to_string(record).unwrap_or_default(),
+            })
+            .collect(),
+    };
+
+    let url = interpolate(&config.url)?;
+    let kind = config.kind.clone().unwrap_or_else(|| detect_kind(&url));
+    let client = Client::new();
+
+    for message in messages {
+        let payload = match kind {
+            WebhookKind::Slack | WebhookKind::Generic => json!({ "text": message }),
+            WebhookKind::Discord => json!({ "content": message }),
+            WebhookKind::Telegram => json!({
+                "chat_id": config.chat_id.as_deref().map(interpolate).transpose()?,
+                "text": message,
+            }),
+        };
+
+        let response = client
+            .post(&url)
+            .json(&payload)
+            .send()
+            .context("can't send webhook notification")?;
+        if !response.status().is_success() {
+            bail!("webhook notification failed with {}", response.status());
+        }
+    }
+
+    info!("sent {:?} webhook notification", kind);
+
+    Ok(())
+}
+
+fn detect_kind(url: &str) -> WebhookKind {
+    if url.contains("hooks.slack.com") {
+        WebhookKind::Slack
+    } else if url.contains("discord.com/api/webhooks") || url.contains("discordapp.com") {
+        WebhookKind::Discord
+    } else if url.contains("api.telegram.org") {
+        WebhookKind::Telegram
+    } else {
+        WebhookKind::Generic
+    }
+}
+
+fn render_summary(template: &str, summary: &RunSummary) -> String {
+    template
+        .replace("{pages}", &summary.pages.to_string())
+        .replace("{failed}", &summary.failed.to_string())
+        .replace("{records}", &summary.records.len().to_string())
+        .replace("{newRecords}", &summary.new_records.len().to_string())
+        .replace("{alerts}", &summary.alerts.len().to_string())
+}
+
+fn send_email(config: &EmailNotify, summary: &RunSummary) -> AnyhowResult<()> {
+    let parse = |address: &str| -> AnyhowResult<Mailbox> {
+        interpolate(address)?
+            .parse()
+            .with_context(|| format!("invalid email address `{}`", address))
+    };
+
+    let mut message = Message::builder()
+        .from(parse(&config.from)?)
+        .subject(&config.subject);
+    for to in &config.to {
+        message = message.to(parse(to)?);
+    }
+    let message = message
+        .body(summary_text(summary))
+        .context("can't build notification email")?;
+
+    let transport = SmtpTransport::from_url(&interpolate(&config.smtp)?)
+        .context("invalid smtp url")?
+        .build();
+    transport
+        .send(&message)
+        .context("can't send notification email")?;
+
+    info!("sent email notification to {}", config.to.join(", "));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::render;
+    use crate::types::ReturnedData;
+    use crate::types::ReturnedDataItem::StringItem;
+
+    #[test]
+    fn render_test() {
+        let record = ReturnedData::from([
+            ("title".to_string(), StringItem("Flat".to_string())),
+            ("price".to_string(), StringItem("500".to_string())),
+        ]);
+
+        assert_eq!(
+            render("New listing: {title} at {price} {missing}", &record),
+            "New listing: Flat at 500 {missing}"
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use log::{debug, info};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, read_to_string, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::env::interpolate;
+use crate::types::OAuth2Config;
+
+/// tokens are refreshed this many seconds before they expire
+const EXPIRY_MARGIN_SECS: u64 = 60;
+
+/// Client-credentials flow, keeping the token cached on disk between runs
+pub struct OAuth2 {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    cache_path: PathBuf,
+    token: Mutex<Option<CachedToken>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedToken {
+    access_token: String,
+    /// unix timestamp in seconds
+    expires_at: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+impl OAuth2 {
+    pub fn new(config: &OAuth2Config) -> AnyhowResult<Self> {
+        let token_url = interpolate(&config.token_url)?;
+        let client_id = interpolate(&config.client_id)?;
+
+        let cache_path = match &config.token_cache {
+            Some(path) => PathBuf::from(interpolate(path)?),
+            None => default_cache_path(&token_url, &client_id),
+        };
+        let token = read_to_string(&cache_path)
+            .ok()
+            .and_then(|cached| serde_json::from_str::<CachedToken>(&cached).ok());
+
+        Ok(OAuth2 {
+            token_url,
+            client_id,
+            client_secret: interpolate(&config.client_secret)?,
+            scopes: config.scopes.clone(),
+            cache_path,
+            token: Mutex::new(token),
+        })
+    }
+
+    /// A valid access token, requesting a new one when the cached one expired
+    pub fn access_token(&self, client: &Client) -> AnyhowResult<String> {
+        let mut token = self.token.lock().unwrap();
+
+        match &*token {
+            Some(cached) if !is_expired(cached) => {
+                debug!("using cached oauth2 token");
+            }
+            _ => *token = Some(self.request_token(client)?),
+        }
+
+        Ok(token.as_ref().unwrap().access_token.clone())
+    }
+
+    /// Drops the current token so the next request fetches a new one
+    pub fn invalidate(&self) {
+        *self.token.lock().unwrap() = None;
+    }
+
+    fn request_token(&self, client: &Client) -> AnyhowResult<CachedToken> {
+        info!("requesting oauth2 token from `{}`", self.token_url);
+
+        let mut form = vec![("grant_type", "client_credentials".to_string())];
+        if !self.scopes.is_empty() {
+            form.push(("scope", self.scopes.join(" ")));
+        }
+
+        let response = client
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&form)
+            .send()
+            .context("can't request oauth2 token")?;
+
+        if !response.status().is_success() {
+            bail!(
+                "oauth2 token endpoint `{}` responded with {}",
+                self.token_url,
+                response.status()
+            );
+        }
+
+        let response = response
+            .json::<TokenResponse>()
+            .context("invalid oauth2 token response")?;
+        let token = CachedToken {
+            access_token: response.access_token,
+            expires_at: response.expires_in.map(|expires_in| now() + expires_in),
+        };
+
+        if let Err(error) = write_token(&self.cache_path, &token) {
+            info!(
+                "can't cache oauth2 token in `{}`: {}",
+                self.cache_path.display(),
+                error
+            );
+        }
+
+        Ok(token)
+    }
+}
+
+fn is_expired(token: &CachedToken) -> bool {
+    match token.expires_at {
+        None => false,
+        Some(expires_at) => now() + EXPIRY_MARGIN_SECS >= expires_at,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Writes the token readable by the current user only, through a new file
+/// renamed over the old one so nothing else can open it on the way
+fn write_token(path: &Path, token: &CachedToken) -> AnyhowResult<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(dir)?;
+    }
+
+    let temporary = path.with_extension(format!("{}.tmp", process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let written = options
+        .open(&temporary)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| Ok(file.write_all(&serde_json::to_vec(token)?)?))
+        .and_then(|_| Ok(fs::rename(&temporary, path)?));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+
+    written
+}
+
+/// `karkinos/oauth2/<sha256 of token url and client id>.json` in the user's
+/// cache directory
+fn default_cache_path(token_url: &str, client_id: &str) -> PathBuf {
+    let key = Sha256::digest(format!("{}\n{}", token_url, client_id));
+
+    cache_dir()
+        .join("karkinos")
+        .join("oauth2")
+        .join(format!("{}.json", hex::encode(key)))
+}
+
+/// `$XDG_CACHE_HOME`, `~/.cache` or `%LOCALAPPDATA%`, the temp directory
+/// without any of them
+fn cache_dir() -> PathBuf {
+    let var = |name| env::var_os(name).filter(|value| !value.is_empty());
+
+    var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| var("LOCALAPPDATA").map(PathBuf::from))
+        .unwrap_or_else(env::temp_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_token_test() {
+        let dir = env::temp_dir().join(format!("karkinos-oauth2-test-{}", process::id()));
+        let path = dir.join("token.json");
+        let token = |access_token: &str| CachedToken {
+            access_token: access_token.to_string(),
+            expires_at: None,
+        };
+
+        write_token(&path, &token("first")).unwrap();
+        write_token(&path, &token("second")).unwrap();
+        let cached = serde_json::from_str::<CachedToken>(&read_to_string(&path).unwrap());
+        assert_eq!(cached.unwrap().access_token, "second");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(default_cache_path("https://auth.example.com/token", "client").ends_with(
+            "karkinos/oauth2/0d64b9123cc38a3ca42305b70d41042bc2537fea432543b8e2596f73591af884.json"
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+#[cfg(feature = "fetch")]
+use anyhow::Context;
+use anyhow::{bail, Result as AnyhowResult};
+#[cfg(feature = "fetch")]
+use chrono::{DateTime, Utc};
+#[cfg(feature = "fetch")]
+use clap::ArgEnum;
+use indexmap::IndexMap;
+use log::warn;
+#[cfg(feature = "fetch")]
+use serde::Serialize;
+use serde_json::Value;
+#[cfg(feature = "fetch")]
+use std::fs::File;
+#[cfg(feature = "fetch")]
+use std::io::{stdout, BufWriter, Write};
+#[cfg(feature = "fetch")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "fetch")]
+use crate::compress::{Compression, Encoder};
+#[cfg(feature = "fetch")]
+use crate::encrypt::{Encrypter, Encryption};
+use crate::types::{JoinConfig, ReturnedData, ReturnedDataItem, ReturnedPage};
+#[cfg(feature = "fetch")]
+use crate::upload::{is_remote, upload};
+
+#[cfg(feature = "fetch")]
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum JsonStyle {
+    Pretty,
+    Compact,
+}
+
+/// Writes `data` as JSON to stdout, a file, or an `s3://`/`gs://` destination,
+/// compressed with `compression` or as the file extension says, then
+/// encrypted with `encryption`. Stdout is pretty and files compact unless
+/// `style` is set
+#[cfg(feature = "fetch")]
+pub fn write_output<T: Serialize>(
+    output: Option<&Path>,
+    compression: Option<Compression>,
+    encryption: Option<&Encryption>,
+    style: Option<JsonStyle>,
+    data: &T,
+) -> AnyhowResult<()> {
+    let compression = compression.or_else(|| output.and_then(Compression::of));
+    let style = style.unwrap_or(match output {
+        None => JsonStyle::Pretty,
+        Some(_) => JsonStyle::Compact,
+    });
+
+    match output {
+        None => {
+            write_json(stdout(), compression, encryption, style, data)?;
+        }
+        Some(output) if is_remote(&output.to_string_lossy()) => {
+            let content_type = match (encryption, compression) {
+                (Some(_), _) => "application/octet-stream",
+                (None, Some(compression)) => compression.content_type(),
+                (None, None) => "application/json",
+            };
+            let body = write_json(vec![], compression, encryption, style, data)?;
+            upload(&output.to_string_lossy(), body, content_type)?;
+        }
+        Some(output) => {
+            let dest = File::create(output)
+                .with_context(|| format!("can't create output file `{}`", output.display()))?;
+            write_json(BufWriter::new(dest), compression, encryption, style, data)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "fetch")]
+fn write_json<W: Write, T: Serialize>(
+    dest: W,
+    compression: Option<Compression>,
+    encryption: Option<&Encryption>,
+    style: JsonStyle,
+    data: &T,
+) -> AnyhowResult<W> {
+    let encrypter = Encrypter::new(encryption, dest).context("can't encrypt output")?;
+    let mut encoder = Encoder::new(compression, encrypter).context("can't write output")?;
+    match style {
+        JsonStyle::Pretty => serde_json::to_writer_pretty(&mut encoder, data),
+        JsonStyle::Compact => serde_json::to_writer(&mut encoder, data),
+    }
+    .context("can't write output")?;
+    let encrypter = encoder.finish().context("can't write output")?;
+    encrypter.finish().context("can't encrypt output")
+}
+
+/// Replaces `{field}` with the record's value, unknown fields are left as-is
+pub fn render(template: &str, record: &ReturnedData) -> String {
+    let mut message = template.to_string();
+
+    for (name, value) in record {
+        let value = match value {
+            ReturnedDataItem::StringItem(value) => value.clone(),
+            other => serde_json::to_string(other).unwrap_or_default(),
+        };
+        message = message.replace(&format!("{{{}}}", name), &value);
+    }
+
+    message
+}
+
+/// `path` with `{runId}` replaced by the run identifier, `{config}` by the
+/// config file name without extension, and `{date}` and `{time}` by the UTC
+/// start of the run as `2024-01-31` and `235959`
+#[cfg(feature = "fetch")]
+pub fn run_path(path: &Path, run_id: &str, config: &str, started: DateTime<Utc>) -> PathBuf {
+    PathBuf::from(
+        path.to_string_lossy()
+            .replace("{runId}", run_id)
+            .replace("{config}", config)
+            .replace("{date}", &started.format("%Y-%m-%d").to_string())
+            .replace("{time}", &started.format("%H%M%S").to_string()),
+    )
+}
+
+/// Records sent to sinks: every page is a record, unless `field` names a list
+/// field whose items are
+pub fn records<'a>(
+    field: Option<&String>,
+    pages: &'a [ReturnedPage],
+) -> AnyhowResult<Vec<&'a ReturnedData>> {
+    let Some(field) = field else {
+        return Ok(pages.iter().map(|page| &page.data).collect());
+    };
+
+    let mut records = vec![];
+    for page in pages {
+        match page.data.get(field) {
+            Some(ReturnedDataItem::DataItems(items)) => records.extend(items),
+            Some(_) => bail!("`records` field `{}` is not a list", field),
+            None => {}
+        }
+    }
+
+    Ok(records)
+}
+
+/// Records mapped by their `key_by` value, records without one are skipped
+pub fn key_records<'a>(
+    key_by: &str,
+    records: Vec<&'a ReturnedData>,
+) -> AnyhowResult<IndexMap<String, &'a ReturnedData>> {
+    let mut keyed = IndexMap::new();
+
+    for record in records {
+        let key = match record.get(key_by) {
+            Some(ReturnedDataItem::StringItem(key)) if !key.is_empty() => key,
+            Some(ReturnedDataItem::DataItems(_)) => bail!("`keyBy` field `{}` is a list", key_by),
+            _ => {
+                warn!("record without `{}` skipped", key_by);
+                continue;
+            }
+        };
+
+        if keyed.insert(key.clone(), record).is_some() {
+            warn!(
+                "duplicate `{}` value `{}`, the last record is kept",
+                key_by, key
+            );
+        }
+    }
+
+    Ok(keyed)
+}
+
+/// Records grouped by their `group_by` value in order of appearance, records
+/// without one are skipped
+pub fn group_records<'a>(
+    group_by: &str,
+    records: Vec<&'a ReturnedData>,
+) -> AnyhowResult<IndexMap<String, Vec<&'a ReturnedData>>> {
+    let mut groups: IndexMap<String, Vec<_>> = IndexMap::new();
+
+    for record in records {
+        let group = match record.get(group_by) {
+            Some(ReturnedDataItem::StringItem(group)) if !group.is_empty() => group,
+            Some(ReturnedDataItem::DataItems(_)) => {
+                bail!("`groupBy` field `{}` is a list", group_by)
+            }
+            _ => {
+                warn!("record without `{}` skipped", group_by);
+                continue;
+            }
+        };
+
+        groups.entry(group.clone()).or_default().push(record);
+    }
+
+    Ok(groups)
+}
+
+/// `left` records merged with the `right` record of the same key
+pub fn join_records(
+    config: &JoinConfig,
+    pages: &[ReturnedPage],
+) -> AnyhowResult<Vec<ReturnedData>> {
+    let right_on = config.right_on.as_ref().unwrap_or(&config.on);
+    let right = key_records(right_on, records(Some(&config.right), pages)?)?;
+
+    let mut joined = vec![];
+    for record in records(Some(&config.left), pages)? {
+        let matched = match record.get(&config.on) {
+            Some(ReturnedDataItem::StringItem(key)) => right.get(key),
+            _ => None,
+        };
+
+        match matched {
+            Some(matched) => joined.push(
+                record
+                    .clone()
+                    .into_iter()
+                    .chain((*matched).clone())
+                    .collect(),
+            ),
+            None if config.keep_unmatched => joined.push(record.clone()),
+            None => {}
+        }
+    }
+
+    Ok(joined)
+}
+
+/// The record with only `fields`, renamed and in their order; values are
+/// field names or dotted paths, missing ones are null
+pub fn select_fields(fields: &IndexMap<String, String>, record: &ReturnedData) -> ReturnedData {
+    let mut nested = None;
+
+    fields
+        .iter()
+        .map(|(name, path)| {
+            // plain names keep lists of records usable as `records`
+            if let Some(value) = record.get(path) {
+                return (name.clone(), value.clone());
+            }
+
+            let nested =
+                nested.get_or_insert_with(|| serde_json::to_value(record).unwrap_or_default());
+            let value = path
+                .split('.')
+                .try_fold(&*nested, |value, segment| select(value, segment));
+            let value = match value {
+                Some(Value::String(value)) => ReturnedDataItem::StringItem(value.clone()),
+                Some(value) => ReturnedDataItem::JsonItem(value.clone()),
+                None => ReturnedDataItem::JsonItem(Value::Null),
+            };
+
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+/// A segment of a dotted path: an object's field or a list item by index
+fn select<'a>(value: &'a Value, segment: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(object) => object.get(segment),
+        Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+/// Splits a page into one page per item of its `root` list field
+pub fn unwrap_root(page: ReturnedPage, root: &str, with_page: bool) -> Vec<ReturnedPage> {
+    let ReturnedPage { mut data, meta } = page;
+
+    let items = match data.shift_remove(root) {
+        Some(ReturnedDataItem::DataItems(items)) => items,
+        _ => vec![],
+    };
+
+    items
+        .into_iter()
+        .map(|item| {
+            // item fields win over page fields of the same name
+            let data = if with_page {
+                data.clone().into_iter().chain(item).collect()
+            } else {
+                item
+            };
+
+            ReturnedPage {
+                data,
+                meta: meta.clone(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ReturnedDataItem::{DataItems, StringItem};
+
+    #[test]
+    #[cfg(feature = "fetch")]
+    fn run_path_test() {
+        let started = DateTime::parse_from_rfc3339("2024-01-31T23:59:58Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            run_path(
+                Path::new("out/{config}-{date}-{time}.json"),
+                "run",
+                "shop",
+                started
+            ),
+            PathBuf::from("out/shop-2024-01-31-235958.json")
+        );
+    }
+
+    #[test]
+    fn select_fields_test() {
+        let offer = ReturnedData::from([("price".to_string(), StringItem("5".to_string()))]);
+        let record = ReturnedData::from([
+            ("title".to_string(), StringItem("Lamp".to_string())),
+            ("sku".to_string(), StringItem("L-1".to_string())),
+            ("offers".to_string(), DataItems(vec![offer])),
+        ]);
+        let fields = IndexMap::from([
+            ("id".to_string(), "sku".to_string()),
+            ("name".to_string(), "title".to_string()),
+            ("price".to_string(), "offers.0.price".to_string()),
+            ("brand".to_string(), "brand".to_string()),
+        ]);
+
+        assert_eq!(
+            select_fields(&fields, &record),
+            ReturnedData::from([
+                ("id".to_string(), StringItem("L-1".to_string())),
+                ("name".to_string(), StringItem("Lamp".to_string())),
+                ("price".to_string(), StringItem("5".to_string())),
+                ("brand".to_string(), ReturnedDataItem::JsonItem(Value::Null)),
+            ])
+        );
+    }
+
+    fn record(fields: &[(&str, &str)]) -> ReturnedData {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), StringItem(value.to_string())))
+            .collect()
+    }
+
+    fn page(data: ReturnedData) -> ReturnedPage {
+        ReturnedPage { data, meta: None }
+    }
+
+    #[test]
+    fn key_records_test() {
+        let first = record(&[("sku", "L-1"), ("title", "Lamp")]);
+        let second = record(&[("sku", "L-1"), ("title", "Lamp, black")]);
+        let unkeyed = record(&[("title", "Desk")]);
+        let empty = record(&[("sku", ""), ("title", "Chair")]);
+
+        let keyed = key_records("sku", vec![&first, &unkeyed, &second, &empty]).unwrap();
+        assert_eq!(keyed.keys().collect::<Vec<_>>(), ["L-1"]);
+        assert_eq!(keyed["L-1"], &second);
+
+        let listed = ReturnedData::from([("sku".to_string(), DataItems(vec![]))]);
+        assert!(key_records("sku", vec![&listed]).is_err());
+    }
+
+    #[test]
+    fn group_records_test() {
+        let lamp = record(&[("category", "lighting"), ("title", "Lamp")]);
+        let desk = record(&[("category", "furniture"), ("title", "Desk")]);
+        let bulb = record(&[("category", "lighting"), ("title", "Bulb")]);
+        let ungrouped = record(&[("title", "Gift card")]);
+
+        let groups = group_records("category", vec![&lamp, &desk, &ungrouped, &bulb]).unwrap();
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["lighting", "furniture"]);
+        assert_eq!(groups["lighting"], [&lamp, &bulb]);
+        assert_eq!(groups["furniture"], [&desk]);
+
+        let listed = ReturnedData::from([("category".to_string(), DataItems(vec![]))]);
+        assert!(group_records("category", vec![&listed]).is_err());
+    }
+
+    #[test]
+    fn join_records_test() {
+        let products = DataItems(vec![
+            record(&[("sku", "L-1"), ("title", "Lamp"), ("price", "0")]),
+            record(&[("sku", "D-1"), ("title", "Desk")]),
+            record(&[("title", "Gift card")]),
+        ]);
+        let prices = DataItems(vec![
+            record(&[("id", "L-1"), ("price", "4")]),
+            record(&[("id", "L-1"), ("price", "5")]),
+        ]);
+        let pages = [page(ReturnedData::from([
+            ("products".to_string(), products),
+            ("prices".to_string(), prices),
+        ]))];
+        let join = |keep_unmatched: bool| JoinConfig {
+            left: "products".to_string(),
+            right: "prices".to_string(),
+            on: "sku".to_string(),
+            right_on: Some("id".to_string()),
+            keep_unmatched,
+        };
+
+        let joined = join_records(&join(true), &pages).unwrap();
+        assert_eq!(
+            joined,
+            [
+                // the last right record of a key wins, and its fields win over the left ones
+                record(&[
+                    ("sku", "L-1"),
+                    ("title", "Lamp"),
+                    ("price", "5"),
+                    ("id", "L-1")
+                ]),
+                record(&[("sku", "D-1"), ("title", "Desk")]),
+                record(&[("title", "Gift card")]),
+            ]
+        );
+        assert_eq!(join_records(&join(false), &pages).unwrap().len(), 1);
+
+        let missing = JoinConfig {
+            right: "reviews".to_string(),
+            ..join(false)
+        };
+        assert!(join_records(&missing, &pages).unwrap().is_empty());
+        let not_a_list = JoinConfig {
+            right: "products".to_string(),
+            left: "title".to_string(),
+            ..join(false)
+        };
+        let titled = [page(record(&[("title", "Lamp")]))];
+        assert!(join_records(&not_a_list, &titled).is_err());
+    }
+
+    #[test]
+    fn unwrap_root_test() {
+        let items = DataItems(vec![
+            record(&[("title", "Lamp"), ("category", "Lamps")]),
+            record(&[("title", "Bulb")]),
+        ]);
+        let listing = ReturnedData::from([
+            ("category".to_string(), StringItem("Lighting".to_string())),
+            ("products".to_string(), items),
+        ]);
+
+        let pages = unwrap_root(page(listing.clone()), "products", false);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].data, record(&[("title", "Bulb")]));
+
+        // item fields win over page fields of the same name
+        let pages = unwrap_root(page(listing.clone()), "products", true);
+        assert_eq!(
+            pages[0].data,
+            record(&[("category", "Lamps"), ("title", "Lamp")])
+        );
+        assert_eq!(
+            pages[1].data,
+            record(&[("category", "Lighting"), ("title", "Bulb")])
+        );
+
+        assert!(unwrap_root(page(listing), "reviews", true).is_empty());
+    }
+}
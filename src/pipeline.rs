@@ -0,0 +1,467 @@
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use scraper::Selector;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::aggregate::aggregate;
+use crate::browser::captured_fields;
+use crate::fetch::Fetcher;
+use crate::jq::Filter;
+use crate::output::{
+    group_records, join_records, key_records, records, select_fields, unwrap_root,
+};
+use crate::plugin::Plugin;
+use crate::preprocess::preprocess_page;
+use crate::provenance::Provenance;
+use crate::report::UrlReport;
+use crate::transform::{keep, record_id};
+use crate::types::{
+    ListPagination, Page, PageMeta, ReturnedData, ReturnedDataItem, ReturnedPage, ScrapeRoot,
+    Target,
+};
+use crate::{
+    compile_templates, diagnostics, graphql, match_template, populate_values_within, Document,
+    Template,
+};
+
+/// The fetch, extract and output steps of a run, with everything the config
+/// needs compiled up front so mistakes fail before any page is fetched
+pub struct Pipeline<'a> {
+    config: &'a ScrapeRoot,
+    templates: Vec<Template<'a>>,
+    transforms: Vec<Plugin>,
+    transform: Option<Filter>,
+    iframes: Vec<Selector>,
+    list_paginations: Vec<&'a ListPagination>,
+}
+
+/// What a run needs besides its config
+#[derive(Default)]
+pub struct ScrapeOptions<'a> {
+    /// pages are read from this archive instead of fetched
+    pub archive: Option<&'a HashMap<String, Page>>,
+    /// threads fetching and extracting pages, from `concurrency` by default
+    pub jobs: Option<usize>,
+    /// add `_meta` to every page, with this run id
+    pub meta: Option<&'a str>,
+    /// add `_diagnostics` to every page
+    pub explain: bool,
+}
+
+/// Side effects of a run as it goes
+pub trait ScrapeHooks {
+    /// A page fetched or read from the archive, before it is extracted
+    fn fetched(&mut self, _url: &str, _page: &Page) -> AnyhowResult<()> {
+        Ok(())
+    }
+
+    /// The records of a scraped url, none when `languages` left it out
+    fn scraped(&mut self, _url: &str, _records: &[ReturnedPage]) -> AnyhowResult<()> {
+        Ok(())
+    }
+}
+
+impl ScrapeHooks for () {}
+
+#[derive(Default)]
+pub struct Scraped {
+    /// records of the scraped urls, in the order of the targets
+    pub pages: Vec<ReturnedPage>,
+    /// the urls scraped, in order
+    pub urls: Vec<String>,
+    pub errors: Vec<anyhow::Error>,
+    pub reports: Vec<UrlReport>,
+}
+
+/// The run's output document and the records it was made from
+pub struct Output {
+    pub document: Value,
+    /// the scraped pages with `output.fields` applied, what every sink gets
+    pub pages: Vec<ReturnedPage>,
+    /// the merged records of `output.join`, sinks get them instead of the pages
+    pub joined: Option<Vec<ReturnedData>>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(config: &'a ScrapeRoot) -> AnyhowResult<Self> {
+        let plugins = config.plugins.as_ref();
+        let transforms = plugins
+            .map_or(&[][..], |plugins| &plugins.transforms)
+            .iter()
+            .map(|path| Plugin::load(path))
+            .collect::<AnyhowResult<Vec<_>>>()?;
+        let transform = match &config.output.transform {
+            Some(transform) => {
+                Some(Filter::compile(transform).context("invalid `output.transform`")?)
+            }
+            None => None,
+        };
+
+        Ok(Pipeline {
+            config,
+            templates: compile_templates(config)?,
+            transforms,
+            transform,
+            iframes: config.iframe_selectors(),
+            list_paginations: config.list_paginations(),
+        })
+    }
+
+    /// The fetcher of the config, with its `plugins.fetcher`
+    pub fn fetcher(&self) -> AnyhowResult<Fetcher<'a>> {
+        let config = self.config;
+        let fetcher = Fetcher::new(&config.config, config.concurrency.per_host)?;
+        let plugins = config.plugins.as_ref();
+
+        Ok(match plugins.and_then(|plugins| plugins.fetcher.as_ref()) {
+            Some(path) => fetcher.with_plugin(Plugin::load(path)?),
+            None => fetcher,
+        })
+    }
+
+    /// Fetches and extracts `targets`; failed pages are in `errors` so the
+    /// others are still scraped
+    pub fn scrape(
+        &self,
+        fetcher: &Fetcher,
+        targets: &[Target],
+        options: &ScrapeOptions,
+        hooks: &mut impl ScrapeHooks,
+    ) -> AnyhowResult<Scraped> {
+        let config = self.config;
+        let concurrency = &config.concurrency;
+        let extract_pool = thread_pool(options.jobs.or(concurrency.jobs))?;
+        let fetch_pool = thread_pool(Some(options.jobs.unwrap_or(concurrency.fetches)))?;
+
+        // with `maxRecords`, targets are scraped a chunk at a time until it is reached
+        let max_records = config.config.max_records;
+        let chunk_size = match max_records {
+            Some(_) => fetch_pool.current_num_threads(),
+            None => targets.len(),
+        };
+        let mut scraped = Scraped::default();
+        for targets in targets.chunks(chunk_size.max(1)) {
+            if max_records.is_some_and(|max_records| scraped.pages.len() >= max_records) {
+                info!("`maxRecords` reached, the remaining urls are not scraped");
+                break;
+            }
+
+            // pages are fetched in parallel but processed in the order of `targets`
+            let order = Fetcher::interleave_by_host(targets);
+            let mut fetched = fetch_pool.install(|| {
+                order
+                    .par_iter()
+                    .map(|&index| {
+                        let target = &targets[index];
+                        let mut url_report = UrlReport::new(&target.url);
+
+                        let fetch_started = Instant::now();
+                        let page = self.fetch(fetcher, target, options, &mut url_report);
+                        url_report.fetch_ms = fetch_started.elapsed().as_millis();
+
+                        (index, &target.url, page, url_report)
+                    })
+                    .collect::<Vec<_>>()
+            });
+            fetched.sort_by_key(|(index, ..)| *index);
+            let fetched = fetched
+                .into_iter()
+                .map(|(_, url, page, url_report)| (url, page, url_report))
+                .collect::<Vec<_>>();
+
+            for (url, page, _) in &fetched {
+                if let Ok(page) = page {
+                    hooks.fetched(url, page)?;
+                }
+            }
+
+            // every page is parsed on its own thread, a parsed page never leaves it
+            let extracted = extract_pool.install(|| {
+                fetched
+                    .into_par_iter()
+                    .map(|(url, page, mut url_report)| {
+                        let page =
+                            page.and_then(|page| self.extract(url, page, options, &mut url_report));
+                        (url, page, url_report)
+                    })
+                    .collect::<Vec<_>>()
+            });
+
+            for (url, page, mut url_report) in extracted {
+                match page {
+                    Ok(None) => {
+                        info!("`{}` skipped, not in one of the `languages`", url);
+                        hooks.scraped(url, &[])?;
+                        scraped.urls.push(url.clone());
+                    }
+                    Ok(Some(page)) => {
+                        let mut page_records = match config.root_field() {
+                            Some((root, field)) => unwrap_root(page, root, field.with_page),
+                            None => vec![page],
+                        };
+                        if let Some(max_records) = max_records {
+                            page_records.truncate(max_records.saturating_sub(scraped.pages.len()));
+                            if page_records.is_empty() {
+                                scraped.reports.push(url_report);
+                                continue;
+                            }
+                        }
+
+                        hooks.scraped(url, &page_records)?;
+                        scraped.pages.extend(page_records);
+                        scraped.urls.push(url.clone());
+                    }
+                    Err(error) => {
+                        error!("{}", error);
+                        url_report.error = Some(error.to_string());
+
+                        scraped.errors.push(error);
+                    }
+                }
+
+                scraped.reports.push(url_report);
+            }
+        }
+
+        Ok(scraped)
+    }
+
+    fn fetch(
+        &self,
+        fetcher: &Fetcher,
+        target: &Target,
+        options: &ScrapeOptions,
+        url_report: &mut UrlReport,
+    ) -> AnyhowResult<Page> {
+        let url = &target.url;
+        if let Some(archive) = options.archive {
+            return archive
+                .get(url)
+                .cloned()
+                .with_context(|| format!("`{}` is not in the warc archive", url));
+        }
+
+        let mut page = fetcher.fetch(target, url_report)?;
+        if !self.iframes.is_empty() {
+            page.frames = fetcher.fetch_frames(target, &page, &self.iframes);
+        }
+        if !self.list_paginations.is_empty() {
+            let pages = fetcher.fetch_list_pages(target, &page, &self.list_paginations)?;
+            page.frames.extend(pages);
+        }
+
+        Ok(page)
+    }
+
+    /// The page's record, `None` when it is not in one of the `languages`
+    fn extract(
+        &self,
+        url: &str,
+        mut page: Page,
+        options: &ScrapeOptions,
+        url_report: &mut UrlReport,
+    ) -> AnyhowResult<Option<ReturnedPage>> {
+        let config = self.config;
+        let extract_started = Instant::now();
+        if let Some(preprocess) = &config.config.preprocess {
+            preprocess_page(preprocess, &mut page);
+        }
+        let document = Document::parse(&page);
+        let template = match_template(&self.templates, url, &document);
+        let (fields, context) = template.map_or(
+            (&config.data, config.context.as_deref()),
+            |(_, template)| (&template.data, template.context.as_deref()),
+        );
+        let mut data = populate_values_within(&document, fields, context);
+        if let Some(browser) = &config.config.browser {
+            data.extend(captured_fields(&browser.capture, &page.captured)?);
+        }
+        if let Some(graphql) = &config.config.graphql {
+            data.extend(graphql::fields(graphql, &page.html)?);
+        }
+        for transform in &self.transforms {
+            data = transform.transform(&data)?;
+        }
+        if !config.record_id.is_empty() {
+            data.insert("_id".to_string(), record_id(&config.record_id, &data));
+        }
+        url_report.record_data(&data, extract_started.elapsed());
+        if !keep(fields, &data) {
+            return Ok(None);
+        }
+        if options.explain {
+            data.insert(
+                "_diagnostics".to_string(),
+                ReturnedDataItem::JsonItem(diagnostics(&document, fields, context)),
+            );
+        }
+
+        let meta = options.meta.map(|run_id| PageMeta {
+            url: url.to_string(),
+            final_url: page.final_url,
+            status: page.status,
+            location: page.location,
+            run_id: run_id.to_string(),
+            template: template.map(|(name, _)| name.to_string()),
+        });
+        Ok(Some(ReturnedPage { data, meta }))
+    }
+
+    /// The document to write for the scraped pages, shaped by `output`
+    pub fn output(&self, scraped: &Scraped, started_at: DateTime<Utc>) -> AnyhowResult<Output> {
+        let config = self.config;
+        let output = &config.output;
+        let written = match output.fields.is_empty() {
+            true => scraped.pages.clone(),
+            false => scraped
+                .pages
+                .iter()
+                .map(|page| ReturnedPage {
+                    data: select_fields(&output.fields, &page.data),
+                    meta: page.meta.clone(),
+                })
+                .collect(),
+        };
+        let joined = match &output.join {
+            Some(join) => Some(join_records(join, &written)?),
+            None => None,
+        };
+        let output_records = match &joined {
+            Some(joined) => joined.iter().collect(),
+            None => records(output.records.as_ref(), &written)?,
+        };
+
+        let data = if let Some(key_by) = &output.key_by {
+            serde_json::to_value(key_records(key_by, output_records.clone())?)?
+        } else if let Some(group_by) = &output.group_by {
+            serde_json::to_value(group_records(group_by, output_records.clone())?)?
+        } else if let Some(joined) = &joined {
+            serde_json::to_value(joined)?
+        } else if config.config.urls.is_empty() && config.root_field().is_none() {
+            // a single `url` keeps writing the page object, null when it was skipped; `urls`
+            // and `root` write a list
+            serde_json::to_value(written.first())?
+        } else {
+            serde_json::to_value(&written)?
+        };
+
+        let document = match &output.aggregate {
+            Some(config) => {
+                let records = match &config.records {
+                    Some(field) => records(Some(field), &written)?,
+                    None => output_records,
+                };
+                let stats = aggregate(config, &records);
+                if config.only {
+                    serde_json::to_value(stats)?
+                } else {
+                    json!({ "aggregate": stats, "data": data })
+                }
+            }
+            None => data,
+        };
+        let document = match &self.transform {
+            Some(transform) => transform
+                .run(document)
+                .context("`output.transform` failed")?,
+            None => document,
+        };
+        let document = if output.provenance {
+            let provenance = Provenance::new(config, started_at, scraped.urls.clone())?;
+            json!({ "provenance": provenance, "data": document })
+        } else {
+            document
+        };
+
+        Ok(Output {
+            document,
+            pages: written,
+            joined,
+        })
+    }
+}
+
+/// `None` sizes the pool by the number of cores
+fn thread_pool(threads: Option<usize>) -> AnyhowResult<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .build()
+        .context("can't start thread pool")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cassette::replaying;
+    use crate::parse_config;
+
+    #[test]
+    fn pipeline_test() {
+        let config = parse_config(
+            r#"
+config:
+  urls: ['https://example.com/1', 'https://example.com/2', 'https://example.com/3']
+  maxRecords: 2
+recordId: [sku]
+data:
+  sku:
+    selector: .sku
+  title:
+    selector: h1
+output:
+  fields: { id: _id, title: title }
+"#,
+        )
+        .unwrap();
+        let pipeline = Pipeline::new(&config).unwrap();
+        let fetcher = pipeline.fetcher().unwrap().with_cassette(replaying(&[
+            (
+                "https://example.com/1",
+                200,
+                "<h1>Lamp</h1><p class=sku>L-1</p>",
+            ),
+            (
+                "https://example.com/2",
+                200,
+                "<h1>Desk</h1><p class=sku>D-1</p>",
+            ),
+            (
+                "https://example.com/3",
+                200,
+                "<h1>Chair</h1><p class=sku>C-1</p>",
+            ),
+        ]));
+        let options = ScrapeOptions {
+            jobs: Some(1),
+            ..Default::default()
+        };
+
+        let targets = config.config.targets();
+        let scraped = pipeline
+            .scrape(&fetcher, &targets, &options, &mut ())
+            .unwrap();
+        assert_eq!(
+            scraped.urls,
+            vec!["https://example.com/1", "https://example.com/2"]
+        );
+
+        let output = pipeline.output(&scraped, Utc::now()).unwrap();
+        let records = output.document.as_array().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["title"], "Desk");
+        assert_eq!(
+            records[1]["id"],
+            serde_json::to_value(record_id(&config.record_id, &scraped.pages[1].data)).unwrap()
+        );
+        assert!(records[0].get("sku").is_none());
+        // sinks get the pages the document was written from
+        assert_eq!(output.pages.len(), 2);
+        assert!(output.pages[0].data.get("sku").is_none());
+        assert!(output.joined.is_none());
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use log::warn;
+use reqwest::blocking::Client;
+use serde::Serialize;
+use std::fs::read;
+use std::sync::Mutex;
+use wasmi::{AsContextMut, Caller, Engine, Extern, Instance, Linker, Memory, Module, Store};
+
+use crate::types::ReturnedData;
+
+/// A WASM plugin. Plugins export `memory` and `alloc(len: i32) -> i32`; an
+/// input is written at an allocated offset and the export called with
+/// `(offset, len)`, outputs come back as `(offset << 32) | len`:
+///
+/// - `transform(offset, len) -> i64` gets and returns a record as JSON
+/// - `sink(offset, len) -> i32` gets the output records, non-zero fails the run
+/// - `fetch(offset, len) -> i64` gets a url and returns the page, it may call
+///   the imported `karkinos.http_get(offset, len) -> i64` the same way
+///
+/// Plugins can't reach the host, network or filesystem otherwise. Calls are
+/// serialized, an instance is single-threaded
+pub struct Plugin {
+    path: String,
+    state: Mutex<(Store<()>, Instance)>,
+}
+
+impl Plugin {
+    pub fn load(path: &str) -> AnyhowResult<Self> {
+        let wasm = read(path).with_context(|| format!("could not read plugin `{}`", path))?;
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm)
+            .map_err(|error| anyhow!("invalid plugin `{}`: {}", path, error))?;
+
+        let mut store = Store::new(&engine, ());
+        let mut linker = Linker::<()>::new(&engine);
+        linker
+            .func_wrap("karkinos", "http_get", http_get)
+            .map_err(|error| anyhow!("{}", error))?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|error| anyhow!("can't start plugin `{}`: {}", path, error))?;
+
+        Ok(Plugin {
+            path: path.to_string(),
+            state: Mutex::new((store, instance)),
+        })
+    }
+
+    pub fn transform(&self, record: &ReturnedData) -> AnyhowResult<ReturnedData> {
+        let output = self.call("transform", &serde_json::to_vec(record)?)?;
+
+        serde_json::from_slice(&output)
+            .with_context(|| format!("plugin `{}` returned invalid JSON", self.path))
+    }
+
+    pub fn fetch(&self, url: &str) -> AnyhowResult<String> {
+        let output = self.call("fetch", url.as_bytes())?;
+
+        String::from_utf8(output)
+            .with_context(|| format!("plugin `{}` returned invalid UTF-8", self.path))
+    }
+
+    pub fn sink<T: Serialize>(&self, records: &[T]) -> AnyhowResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let (store, instance) = &mut *state;
+
+        let (offset, len) = write_input(store, instance, &serde_json::to_vec(records)?)?;
+        let status = instance
+            .get_typed_func::<(i32, i32), i32>(&*store, "sink")
+            .and_then(|sink| sink.call(&mut *store, (offset, len)))
+            .map_err(|error| anyhow!("plugin `{}` failed: {}", self.path, error))?;
+        if status != 0 {
+            bail!("plugin `{}` sink returned {}", self.path, status);
+        }
+
+        Ok(())
+    }
+
+    /// Calls an export taking an input and returning an output
+    fn call(&self, export: &str, input: &[u8]) -> AnyhowResult<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let (store, instance) = &mut *state;
+
+        let (offset, len) = write_input(store, instance, input)?;
+        let output = instance
+            .get_typed_func::<(i32, i32), i64>(&*store, export)
+            .and_then(|function| function.call(&mut *store, (offset, len)))
+            .map_err(|error| anyhow!("plugin `{}` failed: {}", self.path, error))?;
+
+        let memory = memory(store, instance)?;
+        read_output(&mut *store, memory, output)
+    }
+}
+
+fn memory(store: &Store<()>, instance: &Instance) -> AnyhowResult<Memory> {
+    instance
+        .get_memory(store, "memory")
+        .context("plugin doesn't export `memory`")
+}
+
+/// Copies the input into memory the plugin allocated
+fn write_input(
+    store: &mut Store<()>,
+    instance: &Instance,
+    input: &[u8],
+) -> AnyhowResult<(i32, i32)> {
+    let len = i32::try_from(input.len()).context("plugin input is too large")?;
+    let offset = instance
+        .get_typed_func::<i32, i32>(&*store, "alloc")
+        .and_then(|alloc| alloc.call(&mut *store, len))
+        .map_err(|error| anyhow!("plugin `alloc` failed: {}", error))?;
+
+    memory(store, instance)?
+        .write(&mut *store, offset as usize, input)
+        .map_err(|error| anyhow!("plugin `alloc` returned a bad offset: {}", error))?;
+    Ok((offset, len))
+}
+
+fn read_output(store: impl AsContextMut, memory: Memory, output: i64) -> AnyhowResult<Vec<u8>> {
+    let (offset, len) = ((output >> 32) as usize, output as u32 as usize);
+
+    let mut buffer = vec![0; len];
+    memory
+        .read(store, offset, &mut buffer)
+        .map_err(|error| anyhow!("plugin returned a bad output: {}", error))?;
+    Ok(buffer)
+}
+
+/// `karkinos.http_get`, the body of the url or an empty output on failure
+fn http_get(mut caller: Caller<'_, ()>, offset: i32, len: i32) -> i64 {
+    let response = (|| -> AnyhowResult<i64> {
+        let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+            bail!("plugin doesn't export `memory`");
+        };
+        let mut url = vec![0; len as usize];
+        memory
+            .read(&caller, offset as usize, &mut url)
+            .map_err(|error| anyhow!("{}", error))?;
+
+        let body = Client::new()
+            .get(String::from_utf8(url)?)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.bytes())?;
+
+        let Some(Extern::Func(alloc)) = caller.get_export("alloc") else {
+            bail!("plugin doesn't export `alloc`");
+        };
+        let len = i32::try_from(body.len())?;
+        let offset = alloc.typed::<i32, i32>(&caller)?.call(&mut caller, len)?;
+        memory
+            .write(&mut caller, offset as usize, &body)
+            .map_err(|error| anyhow!("{}", error))?;
+
+        Ok(((offset as i64) << 32) | len as i64)
+    })();
+
+    response.unwrap_or_else(|error| {
+        warn!("plugin `http_get` failed: {:#}", error);
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ReturnedDataItem;
+    use std::env;
+
+    #[test]
+    fn transform_test() {
+        // replaces every record, and only sinks empty outputs
+        let module = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                (data (i32.const 0) "{\"title\":\"plugged\"}")
+                (func (export "alloc") (param $len i32) (result i32)
+                    (global.get $next)
+                    (global.set $next (i32.add (global.get $next) (local.get $len))))
+                (func (export "transform") (param i32 i32) (result i64)
+                    (i64.const 19))
+                (func (export "sink") (param i32) (param $len i32) (result i32)
+                    (i32.ne (local.get $len) (i32.const 2))))"#,
+        )
+        .unwrap();
+        let path = env::temp_dir().join(format!("karkinos-test-{}.wasm", std::process::id()));
+        std::fs::write(&path, module).unwrap();
+
+        let plugin = Plugin::load(path.to_str().unwrap()).unwrap();
+        let record = ReturnedData::from([(
+            "title".to_string(),
+            ReturnedDataItem::StringItem("Lamp".to_string()),
+        )]);
+        assert_eq!(
+            plugin.transform(&record).unwrap(),
+            ReturnedData::from([(
+                "title".to_string(),
+                ReturnedDataItem::StringItem("plugged".to_string()),
+            )])
+        );
+        assert!(plugin.sink::<ReturnedData>(&[]).is_ok());
+        assert!(plugin.sink(&[record]).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
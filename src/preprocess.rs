@@ -0,0 +1,108 @@
+use html5ever::tendril::StrTendril;
+use scraper::{Html, Node};
+
+use crate::types::{Page, PreprocessConfig};
+
+/// Cleans the page and its iframe and list pages up before anything is
+/// extracted from them
+pub fn preprocess_page(config: &PreprocessConfig, page: &mut Page) {
+    page.html = preprocess(config, &page.html);
+    for html in page.frames.values_mut() {
+        *html = preprocess(config, html);
+    }
+}
+
+/// `html` without the nodes `config` removes, whitespace collapsed with `minify`
+pub fn preprocess(config: &PreprocessConfig, html: &str) -> String {
+    let mut document = Html::parse_document(html);
+
+    let removed = document
+        .tree
+        .nodes()
+        .filter(|node| match node.value() {
+            Node::Comment(_) => config.remove_comments,
+            Node::Element(element) => match element.name() {
+                "script" | "noscript" => config.remove_scripts,
+                "style" => config.remove_styles,
+                _ => false,
+            },
+            _ => false,
+        })
+        .map(|node| node.id())
+        .collect::<Vec<_>>();
+    for id in removed {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+
+    if config.minify {
+        let texts = document
+            .tree
+            .nodes()
+            .filter(|node| node.value().is_text())
+            .filter(|node| {
+                !node.ancestors().any(|ancestor| {
+                    ancestor.value().as_element().is_some_and(|element| {
+                        ["pre", "textarea", "script", "style"].contains(&element.name())
+                    })
+                })
+            })
+            .map(|node| node.id())
+            .collect::<Vec<_>>();
+        for id in texts {
+            if let Some(mut node) = document.tree.get_mut(id) {
+                if let Node::Text(text) = node.value() {
+                    text.text = StrTendril::from(collapse_whitespace(&text.text));
+                }
+            }
+        }
+    }
+
+    document.root_element().html()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut whitespace = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            whitespace = true;
+            continue;
+        }
+        if whitespace {
+            collapsed.push(' ');
+            whitespace = false;
+        }
+        collapsed.push(c);
+    }
+    if whitespace {
+        collapsed.push(' ');
+    }
+
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preprocess_test() {
+        let html = r#"<html><head><style>p { color: red }</style></head><body>
+            <!-- price -->
+            <p>Lamp   <b>$20</b></p><script>window.data = {"price": 20}</script>
+            <pre>a  b</pre></body></html>"#;
+        let config = PreprocessConfig {
+            remove_scripts: true,
+            remove_styles: true,
+            remove_comments: true,
+            minify: true,
+        };
+
+        assert_eq!(
+            preprocess(&config, html),
+            "<html><head></head><body>  <p>Lamp <b>$20</b></p> <pre>a  b</pre></body></html>"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Result as AnyhowResult;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::types::ScrapeRoot;
+
+/// Where an output comes from, written with `output.provenance`
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    /// SHA-256 of the parsed config, so formatting and comments don't change it
+    pub config_hash: String,
+    pub version: &'static str,
+    pub started_at: String,
+    pub finished_at: String,
+    /// urls the records were extracted from
+    pub urls: Vec<String>,
+}
+
+impl Provenance {
+    pub fn new(
+        config: &ScrapeRoot,
+        started: DateTime<Utc>,
+        urls: Vec<String>,
+    ) -> AnyhowResult<Self> {
+        Ok(Provenance {
+            config_hash: config_hash(config)?,
+            version: env!("CARGO_PKG_VERSION"),
+            started_at: started.to_rfc3339_opts(SecondsFormat::Secs, true),
+            finished_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            urls,
+        })
+    }
+}
+
+fn config_hash(config: &ScrapeRoot) -> AnyhowResult<String> {
+    Ok(hex::encode(Sha256::digest(serde_json::to_vec(config)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_config;
+
+    #[test]
+    fn config_hash_test() {
+        let config = |yaml| config_hash(&parse_config(yaml).unwrap()).unwrap();
+
+        assert_eq!(
+            config("config:\n  url: https://example.com\ndata:\n  title: { selector: h1 }\n"),
+            config("# shop\nconfig: { url: 'https://example.com' }\ndata: { title: { selector: h1 } }\n")
+        );
+        assert_ne!(
+            config("config: { url: 'https://example.com' }\ndata: { title: { selector: h1 } }"),
+            config("config: { url: 'https://example.com' }\ndata: { title: { selector: h2 } }")
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{Context, Result as AnyhowResult};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::{self, read_to_string, File};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::{BrowserConfig, Page, PaginationConfig, Target};
+
+/// Rendered pages kept on disk so re-running a config skips the render
+pub struct RenderCache {
+    dir: PathBuf,
+    ttl: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedPage {
+    final_url: String,
+    status: u16,
+    html: String,
+    #[serde(default)]
+    captured: IndexMap<String, Value>,
+    /// unix timestamp in seconds
+    rendered_at: u64,
+}
+
+impl RenderCache {
+    pub fn new(dir: &str, ttl: Option<u64>) -> Self {
+        RenderCache {
+            dir: PathBuf::from(dir),
+            ttl,
+        }
+    }
+
+    pub fn get(
+        &self,
+        target: &Target,
+        config: &BrowserConfig,
+        pagination: Option<&PaginationConfig>,
+    ) -> Option<Page> {
+        let cached = read_to_string(self.path(target, config, pagination)).ok()?;
+        let cached = serde_json::from_str::<CachedPage>(&cached).ok()?;
+
+        if let Some(ttl) = self.ttl {
+            if now().saturating_sub(cached.rendered_at) > ttl {
+                return None;
+            }
+        }
+
+        Some(Page {
+            html: cached.html,
+            final_url: cached.final_url,
+            status: cached.status,
+            captured: cached.captured,
+            ..Default::default()
+        })
+    }
+
+    pub fn put(
+        &self,
+        target: &Target,
+        config: &BrowserConfig,
+        pagination: Option<&PaginationConfig>,
+        page: &Page,
+    ) -> AnyhowResult<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("can't create render cache `{}`", self.dir.display()))?;
+
+        let cached = CachedPage {
+            final_url: page.final_url.clone(),
+            status: page.status,
+            html: page.html.clone(),
+            captured: page.captured.clone(),
+            rendered_at: now(),
+        };
+        let dest = File::create(self.path(target, config, pagination))
+            .context("can't write render cache")?;
+
+        serde_json::to_writer(dest, &cached).context("can't write render cache")
+    }
+
+    fn path(
+        &self,
+        target: &Target,
+        config: &BrowserConfig,
+        pagination: Option<&PaginationConfig>,
+    ) -> PathBuf {
+        let key = cache_key(target, config, pagination);
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+/// Hash of everything that changes what a render produces
+fn cache_key(
+    target: &Target,
+    config: &BrowserConfig,
+    pagination: Option<&PaginationConfig>,
+) -> String {
+    let key = json!({
+        "url": target.url,
+        "actions": target.actions,
+        "pagination": pagination,
+        "waitFor": config.wait_for,
+        "wait": config.wait,
+        "capture": config.capture,
+        "viewport": config.viewport,
+        "locale": config.locale,
+        "timezone": config.timezone,
+        "pierceShadow": config.pierce_shadow,
+    });
+
+    hex::encode(Sha256::digest(key.to_string()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{BrowserAction, Timeouts};
+    use std::{env, process};
+
+    fn target(url: &str, actions: Vec<BrowserAction>) -> Target {
+        Target {
+            url: url.to_string(),
+            timeouts: Timeouts::default(),
+            actions,
+        }
+    }
+
+    fn browser(yaml: &str) -> BrowserConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn cache_key_test() {
+        let page = target("https://example.com/a", vec![]);
+        let key = cache_key(&page, &browser("{}"), None);
+
+        assert_eq!(key, cache_key(&page, &browser("{}"), None));
+        // how a render is run doesn't change what it produces
+        let unrelated =
+            browser("{ timeout: 5000, executable: chrome, cacheTtl: 60, cache: renders }");
+        assert_eq!(key, cache_key(&page, &unrelated, None));
+
+        let other = target("https://example.com/b", vec![]);
+        assert_ne!(key, cache_key(&other, &browser("{}"), None));
+        let clicked = target(
+            "https://example.com/a",
+            vec![BrowserAction::Click("#more".into())],
+        );
+        assert_ne!(key, cache_key(&clicked, &browser("{}"), None));
+        assert_ne!(
+            key,
+            cache_key(&page, &browser("{ waitFor: '#list' }"), None)
+        );
+        assert_ne!(
+            key,
+            cache_key(&page, &browser("{ pierceShadow: true }"), None)
+        );
+        let scroll = serde_yaml::from_str::<PaginationConfig>("{ infiniteScroll: { items: li } }");
+        assert_ne!(
+            key,
+            cache_key(&page, &browser("{}"), Some(&scroll.unwrap()))
+        );
+    }
+
+    #[test]
+    fn render_cache_test() {
+        let dir = env::temp_dir().join(format!("karkinos-render-cache-test-{}", process::id()));
+        let dir = dir.to_string_lossy();
+        let config = browser("{}");
+        let page = target("https://example.com/a", vec![]);
+        let rendered = Page {
+            html: "<h1>Lamp</h1>".to_string(),
+            final_url: "https://example.com/a#top".to_string(),
+            status: 200,
+            ..Default::default()
+        };
+
+        let cache = RenderCache::new(&dir, None);
+        assert!(cache.get(&page, &config, None).is_none());
+        cache.put(&page, &config, None, &rendered).unwrap();
+        let cached = cache.get(&page, &config, None).unwrap();
+        assert_eq!(cached.html, rendered.html);
+        assert_eq!(cached.final_url, rendered.final_url);
+        assert!(cache.get(&page, &browser("{ wait: 100 }"), None).is_none());
+
+        let stale = CachedPage {
+            final_url: rendered.final_url.clone(),
+            status: 200,
+            html: rendered.html.clone(),
+            captured: IndexMap::new(),
+            rendered_at: now() - 120,
+        };
+        let path = cache.path(&page, &config, None);
+        fs::write(&path, serde_json::to_string(&stale).unwrap()).unwrap();
+        assert!(RenderCache::new(&dir, Some(60))
+            .get(&page, &config, None)
+            .is_none());
+        assert!(RenderCache::new(&dir, Some(600))
+            .get(&page, &config, None)
+            .is_some());
+
+        fs::remove_dir_all(&*dir).unwrap();
+    }
+}
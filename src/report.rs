@@ -0,0 +1,106 @@
+use anyhow::{Context, Result as AnyhowResult};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::types::{BlockDetector, ReturnedData, ReturnedDataItem};
+
+/// Structured summary of a run, written with `--report`
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RunReport {
+    pub run_id: String,
+    pub success: bool,
+    pub duration_ms: u128,
+    pub urls: Vec<UrlReport>,
+    pub warnings: Vec<String>,
+    /// messages of the matched `alerts` rules
+    pub alerts: Vec<String>,
+    /// urls that got an anti-bot block page
+    pub blocked: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlReport {
+    pub url: String,
+    pub final_url: Option<String>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub fetch_ms: u128,
+    pub extract_ms: u128,
+    pub retries: u32,
+    pub cache_hit: bool,
+    /// number of records per list field
+    pub records: ReportCounts,
+    /// fields whose selector matched nothing and fell back to an empty value
+    pub defaulted_fields: Vec<String>,
+    /// anti-bot block page detected, the last one when retried
+    pub blocked: Option<BlockDetector>,
+}
+
+pub type ReportCounts = BTreeMap<String, usize>;
+
+impl RunReport {
+    pub fn finish(&mut self, started: Instant, success: bool) {
+        self.duration_ms = started.elapsed().as_millis();
+        self.success = success;
+    }
+
+    pub fn write(&self, path: &Path) -> AnyhowResult<()> {
+        let dest = File::create(path)
+            .with_context(|| format!("can't create report file `{}`", path.display()))?;
+
+        serde_json::to_writer_pretty(dest, self).context("can't write report")
+    }
+}
+
+impl UrlReport {
+    pub fn new(url: &str) -> Self {
+        UrlReport {
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record_data(&mut self, data: &ReturnedData, extract_time: Duration) {
+        self.extract_ms = extract_time.as_millis();
+        collect_stats(data, "", &mut self.records, &mut self.defaulted_fields);
+        self.defaulted_fields.sort();
+        self.defaulted_fields.dedup();
+    }
+}
+
+fn collect_stats(
+    data: &ReturnedData,
+    prefix: &str,
+    records: &mut ReportCounts,
+    defaulted: &mut Vec<String>,
+) {
+    for (name, value) in data {
+        let path = format!("{}{}", prefix, name);
+
+        match value {
+            ReturnedDataItem::StringItem(value) => {
+                if value.is_empty() {
+                    defaulted.push(path);
+                }
+            }
+            ReturnedDataItem::JsonItem(value) => {
+                if value.is_null() {
+                    defaulted.push(path);
+                }
+            }
+            ReturnedDataItem::DataItems(items) => {
+                *records.entry(path.clone()).or_default() += items.len();
+
+                let inner_prefix = format!("{}.", path);
+                items
+                    .iter()
+                    .for_each(|item| collect_stats(item, &inner_prefix, records, defaulted));
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::{Context, Result as AnyhowResult};
+use clap::ArgEnum;
+use schemars::schema_for;
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::path::Path;
+
+use crate::types::ScrapeRoot;
+
+#[derive(ArgEnum, Clone, Copy)]
+pub enum SchemaFormat {
+    Json,
+    Yaml,
+}
+
+/// Writes the JSON Schema of the config file to stdout or `out`
+pub fn write_schema(out: Option<&Path>, format: SchemaFormat) -> AnyhowResult<()> {
+    let schema = schema_for!(ScrapeRoot);
+
+    let mut body = match format {
+        SchemaFormat::Json => serde_json::to_string_pretty(&schema)?,
+        SchemaFormat::Yaml => serde_yaml::to_string(&schema)?,
+    };
+    if !body.ends_with('\n') {
+        body.push('\n');
+    }
+
+    match out {
+        None => stdout().write_all(body.as_bytes())?,
+        Some(out) => File::create(out)
+            .with_context(|| format!("can't create schema file `{}`", out.display()))?
+            .write_all(body.as_bytes())?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use reqwest::blocking::Request;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::env::interpolate;
+use crate::sigv4::{hmac, Credentials, Signer};
+use crate::types::{HmacSign, SigV4Sign, SignConfig, SignatureEncoding};
+
+/// `sign` config with environment variables resolved
+pub enum RequestSigner {
+    Hmac(HmacSign),
+    SigV4(SigV4Sign, Credentials),
+    Command(String),
+}
+
+impl RequestSigner {
+    pub fn new(config: &SignConfig) -> AnyhowResult<Self> {
+        match config {
+            SignConfig {
+                hmac: Some(hmac), ..
+            } => Ok(RequestSigner::Hmac(HmacSign {
+                secret: interpolate(&hmac.secret)?,
+                ..hmac.clone()
+            })),
+            SignConfig {
+                sigv4: Some(sigv4), ..
+            } => Ok(RequestSigner::SigV4(
+                sigv4.clone(),
+                Credentials::from_env()?,
+            )),
+            SignConfig {
+                command: Some(command),
+                ..
+            } => Ok(RequestSigner::Command(interpolate(command)?)),
+            _ => bail!("`sign` needs one of `hmac`, `sigv4` or `command`"),
+        }
+    }
+
+    /// Headers to add to the request
+    pub fn sign(
+        &self,
+        request: &Request,
+        now: DateTime<Utc>,
+    ) -> AnyhowResult<Vec<(String, String)>> {
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .unwrap_or_default();
+        let timestamp = now.timestamp().to_string();
+
+        match self {
+            RequestSigner::Hmac(config) => Ok(sign_hmac(config, request, body, &timestamp)),
+            RequestSigner::SigV4(config, credentials) => {
+                let headers = request
+                    .headers()
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        Some((name.to_string(), value.to_str().ok()?.to_string()))
+                    })
+                    .collect::<Vec<_>>();
+                let signer = Signer {
+                    credentials,
+                    region: &config.region,
+                    service: &config.service,
+                };
+
+                Ok(signer.sign(
+                    request.method().as_str(),
+                    request.url(),
+                    &headers,
+                    body,
+                    now,
+                ))
+            }
+            RequestSigner::Command(command) => run_command(command, request, body, &timestamp),
+        }
+    }
+}
+
+fn sign_hmac(
+    config: &HmacSign,
+    request: &Request,
+    body: &[u8],
+    timestamp: &str,
+) -> Vec<(String, String)> {
+    let url = request.url();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    let message = config
+        .message
+        .replace("{method}", request.method().as_str())
+        .replace("{path}", &path)
+        .replace("{url}", url.as_str())
+        .replace("{host}", url.host_str().unwrap_or_default())
+        .replace("{timestamp}", timestamp)
+        .replace("{bodySha256}", &hex::encode(Sha256::digest(body)))
+        .replace("{body}", &String::from_utf8_lossy(body));
+
+    let signature = hmac(config.secret.as_bytes(), message.as_bytes());
+    let signature = match config.encoding {
+        SignatureEncoding::Hex => hex::encode(signature),
+        SignatureEncoding::Base64 => STANDARD.encode(signature),
+    };
+
+    let mut headers = vec![(
+        config.header.clone(),
+        format!("{}{}", config.prefix, signature),
+    )];
+    if let Some(header) = &config.timestamp_header {
+        headers.push((header.clone(), timestamp.to_string()));
+    }
+    headers
+}
+
+fn run_command(
+    command: &str,
+    request: &Request,
+    body: &[u8],
+    timestamp: &str,
+) -> AnyhowResult<Vec<(String, String)>> {
+    let mut process = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("KARKINOS_METHOD", request.method().as_str())
+        .env("KARKINOS_URL", request.url().as_str())
+        .env("KARKINOS_TIMESTAMP", timestamp)
+        .env("KARKINOS_BODY_SHA256", hex::encode(Sha256::digest(body)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("can't run signing command `{}`", command))?;
+
+    // the command may not read its input, a closed pipe is fine then
+    if let Some(mut stdin) = process.stdin.take() {
+        let _ = stdin.write_all(body);
+    }
+    let output = process.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "signing command `{}` failed with {}",
+            command,
+            output.status
+        );
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.split_once(':') {
+            Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+            None => bail!("signing command printed `{}`, not `Name: value`", line),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+    #[test]
+    fn sign_hmac_test() {
+        let config = HmacSign {
+            secret: "key".to_string(),
+            message: "{body}".to_string(),
+            header: "X-Signature".to_string(),
+            prefix: "HMAC ".to_string(),
+            timestamp_header: Some("X-Timestamp".to_string()),
+            encoding: SignatureEncoding::Hex,
+        };
+        let request = Request::new(Method::POST, "https://api.example.com/".parse().unwrap());
+        let body = b"The quick brown fox jumps over the lazy dog";
+
+        assert_eq!(
+            sign_hmac(&config, &request, body, "1700000000"),
+            vec![
+                (
+                    "X-Signature".to_string(),
+                    "HMAC f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+                        .to_string()
+                ),
+                ("X-Timestamp".to_string(), "1700000000".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{Context, Result as AnyhowResult};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// AWS credentials, read from the standard `AWS_*` environment variables
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+impl Credentials {
+    pub fn from_env() -> AnyhowResult<Self> {
+        Ok(Credentials {
+            access_key: env::var("AWS_ACCESS_KEY_ID").context("AWS_ACCESS_KEY_ID is not set")?,
+            secret_key: env::var("AWS_SECRET_ACCESS_KEY")
+                .context("AWS_SECRET_ACCESS_KEY is not set")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// Signs requests to one AWS service in one region
+pub struct Signer<'a> {
+    pub credentials: &'a Credentials,
+    pub region: &'a str,
+    pub service: &'a str,
+}
+
+impl<'a> Signer<'a> {
+    /// Headers (besides `host`) to send for a request signed with AWS Signature Version 4
+    pub fn sign(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(String, String)],
+        payload: &[u8],
+        now: DateTime<Utc>,
+    ) -> Vec<(String, String)> {
+        let Signer {
+            credentials,
+            region,
+            service,
+        } = self;
+
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(payload));
+
+        let mut signed = headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+            .collect::<BTreeMap<_, _>>();
+        signed.insert("host".into(), host(url));
+        signed.insert("x-amz-date".into(), amz_date.clone());
+        signed.insert("x-amz-content-sha256".into(), payload_hash.clone());
+        if let Some(token) = &credentials.session_token {
+            signed.insert("x-amz-security-token".into(), token.clone());
+        }
+
+        let (signed_names, signature) = signature(
+            method,
+            &canonical_uri(url),
+            &canonical_query(url),
+            &signed,
+            &payload_hash,
+            &credentials.secret_key,
+            region,
+            service,
+            &amz_date,
+        );
+
+        let scope = format!("{}/{}/{}/aws4_request", &amz_date[..8], region, service);
+        signed.insert(
+            "authorization".into(),
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                credentials.access_key, scope, signed_names, signature
+            ),
+        );
+        signed.remove("host");
+
+        signed.into_iter().collect()
+    }
+}
+
+/// Signed header names and the hex signature
+#[allow(clippy::too_many_arguments)]
+fn signature(
+    method: &str,
+    canonical_uri: &str,
+    canonical_query: &str,
+    headers: &BTreeMap<String, String>,
+    payload_hash: &str,
+    secret_key: &str,
+    region: &str,
+    service: &str,
+    amz_date: &str,
+) -> (String, String) {
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect::<String>();
+    let signed_names = headers.keys().cloned().collect::<Vec<_>>().join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, canonical_uri, canonical_query, canonical_headers, signed_names, payload_hash
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = [date, region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", secret_key).into_bytes(), |key, part| {
+            hmac(&key, part.as_bytes())
+        });
+
+    (
+        signed_names,
+        hex::encode(hmac(&key, string_to_sign.as_bytes())),
+    )
+}
+
+pub fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data);
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+fn canonical_uri(url: &Url) -> String {
+    // `Url` already percent-encodes the path the way SigV4 expects for S3
+    url.path().to_string()
+}
+
+fn canonical_query(url: &Url) -> String {
+    let mut pairs = url
+        .query_pairs()
+        .map(|(name, value)| (encode(&name), encode(&value)))
+        .collect::<Vec<_>>();
+    pairs.sort();
+
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent-encodes everything except the unreserved characters
+pub fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sigv4::signature;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn signature_test() {
+        // "GET Object" example from the AWS S3 SigV4 documentation
+        let empty_hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let headers = BTreeMap::from([
+            (
+                "host".to_string(),
+                "examplebucket.s3.amazonaws.com".to_string(),
+            ),
+            ("range".to_string(), "bytes=0-9".to_string()),
+            ("x-amz-content-sha256".to_string(), empty_hash.to_string()),
+            ("x-amz-date".to_string(), "20130524T000000Z".to_string()),
+        ]);
+
+        let (signed_names, signature) = signature(
+            "GET",
+            "/test.txt",
+            "",
+            &headers,
+            empty_hash,
+            "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+            "us-east-1",
+            "s3",
+            "20130524T000000Z",
+        );
+
+        assert_eq!(signed_names, "host;range;x-amz-content-sha256;x-amz-date");
+        assert_eq!(
+            signature,
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+}
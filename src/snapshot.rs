@@ -0,0 +1,148 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use indexmap::IndexMap;
+use serde_json::Value;
+use std::fs::{read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+use crate::output::{write_output, JsonStyle};
+use crate::preprocess::preprocess_page;
+use crate::types::Page;
+use crate::{compile_templates, load_config, match_template, populate_values_within, Document};
+
+/// Extracts every HTML file of the fixtures directory and compares the
+/// records with the golden file, or rewrites it with `update`
+pub fn test(
+    input: &Path,
+    fixtures: Option<&Path>,
+    golden: Option<&Path>,
+    update: bool,
+) -> AnyhowResult<()> {
+    let config = load_config(input)?;
+    let fixtures = fixtures.map_or_else(|| input.with_extension("fixtures"), PathBuf::from);
+    let golden = golden.map_or_else(|| input.with_extension("golden.json"), PathBuf::from);
+    let templates = compile_templates(&config)?;
+
+    let mut actual = IndexMap::new();
+    for path in fixture_files(&fixtures)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut page = Page {
+            html: read_to_string(&path)
+                .with_context(|| format!("could not read file `{}`", path.display()))?,
+            ..Default::default()
+        };
+        if let Some(preprocess) = &config.config.preprocess {
+            preprocess_page(preprocess, &mut page);
+        }
+
+        let document = Document::parse(&page);
+        let template = match_template(&templates, &name, &document);
+        let (fields, context) = template.map_or(
+            (&config.data, config.context.as_deref()),
+            |(_, template)| (&template.data, template.context.as_deref()),
+        );
+        actual.insert(
+            name.into_owned(),
+            populate_values_within(&document, fields, context),
+        );
+    }
+    let actual = serde_json::to_value(actual)?;
+
+    if update {
+        write_output(Some(&golden), None, None, Some(JsonStyle::Pretty), &actual)?;
+        println!("updated `{}`", golden.display());
+        return Ok(());
+    }
+
+    let expected = read_to_string(&golden).with_context(|| {
+        format!(
+            "could not read golden file `{}`, create it with `--update`",
+            golden.display()
+        )
+    })?;
+    let expected = serde_json::from_str(&expected)
+        .with_context(|| format!("invalid golden file `{}`", golden.display()))?;
+
+    let differences = differences(&expected, &actual, "");
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if !differences.is_empty() {
+        bail!(
+            "{} difference(s) with `{}`",
+            differences.len(),
+            golden.display()
+        );
+    }
+
+    println!(
+        "{} fixture(s) match `{}`",
+        actual.as_object().map_or(0, |fixtures| fixtures.len()),
+        golden.display()
+    );
+    Ok(())
+}
+
+/// `.html` and `.htm` files, sorted by name
+fn fixture_files(dir: &Path) -> AnyhowResult<Vec<PathBuf>> {
+    let mut files = read_dir(dir)
+        .with_context(|| format!("could not read fixtures directory `{}`", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<AnyhowResult<Vec<_>>>()?;
+    files.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "html" || extension == "htm")
+    });
+    files.sort();
+
+    Ok(files)
+}
+
+/// One line per differing value, objects are compared key by key
+fn differences(expected: &Value, actual: &Value, path: &str) -> Vec<String> {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let mut keys = expected.keys().collect::<Vec<_>>();
+            keys.extend(actual.keys().filter(|key| !expected.contains_key(*key)));
+
+            keys.into_iter()
+                .flat_map(|key| {
+                    let path = match path {
+                        "" => key.clone(),
+                        path => format!("{}.{}", path, key),
+                    };
+                    match (expected.get(key), actual.get(key)) {
+                        (Some(expected), Some(actual)) => differences(expected, actual, &path),
+                        (Some(_), None) => vec![format!("{}: missing", path)],
+                        (None, Some(actual)) => vec![format!("{}: unexpected {}", path, actual)],
+                        (None, None) => vec![],
+                    }
+                })
+                .collect()
+        }
+        (expected, actual) if expected == actual => vec![],
+        (expected, actual) => vec![format!("{}: expected {}, got {}", path, expected, actual)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn differences_test() {
+        let expected =
+            json!({"a.html": {"title": "Lamp", "price": "10"}, "b.html": {"title": "Desk"}});
+        let actual =
+            json!({"a.html": {"title": "Lamp", "price": "12"}, "c.html": {"title": "Chair"}});
+
+        assert_eq!(
+            differences(&expected, &actual, ""),
+            vec![
+                "a.html.price: expected \"10\", got \"12\"",
+                "b.html: missing",
+                "c.html: unexpected {\"title\":\"Chair\"}",
+            ]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use kafka::producer::{Producer, Record, RequiredAcks};
+use log::{debug, info};
+use reqwest::Url;
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::env::interpolate;
+use crate::types::{ReturnedData, StreamOutput};
+
+const KAFKA_ACK_TIMEOUT_SECS: u64 = 5;
+
+/// Publishes every record as JSON to a Kafka topic or NATS subject as soon as
+/// its page is scraped
+pub enum Publisher {
+    Kafka {
+        producer: Box<Producer>,
+        topic: String,
+    },
+    Nats {
+        connection: Nats,
+        subject: String,
+    },
+}
+
+impl Publisher {
+    pub fn connect(config: &StreamOutput) -> AnyhowResult<Self> {
+        let url = interpolate(&config.url)?;
+
+        if let Some(brokers) = url.strip_prefix("kafka://") {
+            let hosts = brokers.split(',').map(String::from).collect::<Vec<_>>();
+            info!("publishing records to kafka topic `{}`", config.topic);
+
+            let producer = Producer::from_hosts(hosts)
+                .with_ack_timeout(Duration::from_secs(KAFKA_ACK_TIMEOUT_SECS))
+                .with_required_acks(RequiredAcks::One)
+                .create()
+                .context("can't connect to kafka")?;
+
+            Ok(Publisher::Kafka {
+                producer: Box::new(producer),
+                topic: config.topic.clone(),
+            })
+        } else if url.starts_with("nats://") {
+            info!("publishing records to nats subject `{}`", config.topic);
+
+            Ok(Publisher::Nats {
+                connection: Nats::connect(&url)?,
+                subject: config.topic.clone(),
+            })
+        } else {
+            bail!("unsupported stream url, expected `kafka://` or `nats://`")
+        }
+    }
+
+    pub fn publish(&mut self, record: &ReturnedData) -> AnyhowResult<()> {
+        let payload = serde_json::to_vec(record)?;
+
+        match self {
+            Publisher::Kafka { producer, topic } => producer
+                .send(&Record::from_value(topic.as_str(), payload))
+                .context("can't publish record to kafka"),
+            Publisher::Nats {
+                connection,
+                subject,
+            } => connection.publish(subject, &payload),
+        }
+    }
+
+    /// Waits until everything published so far was accepted
+    pub fn flush(&mut self) -> AnyhowResult<()> {
+        match self {
+            Publisher::Kafka { .. } => Ok(()),
+            Publisher::Nats { connection, .. } => connection.flush(),
+        }
+    }
+}
+
+/// Minimal NATS client, only what publishing needs
+pub struct Nats {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Nats {
+    fn connect(url: &str) -> AnyhowResult<Self> {
+        let url = Url::parse(url).with_context(|| format!("invalid nats url `{}`", url))?;
+        let address = format!(
+            "{}:{}",
+            url.host_str().unwrap_or("localhost"),
+            url.port().unwrap_or(4222)
+        );
+
+        let writer = TcpStream::connect(&address)
+            .with_context(|| format!("can't connect to nats at `{}`", address))?;
+        let mut nats = Nats {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        };
+
+        let info = nats.read_line()?;
+        if !info.starts_with("INFO") {
+            bail!("unexpected nats greeting: {}", info);
+        }
+
+        let mut connect = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "karkinos",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        match (url.username(), url.password()) {
+            ("", _) => {}
+            (token, None) => connect["auth_token"] = json!(token),
+            (user, Some(pass)) => {
+                connect["user"] = json!(user);
+                connect["pass"] = json!(pass);
+            }
+        }
+        write!(nats.writer, "CONNECT {}\r\n", connect)?;
+        nats.flush()?;
+
+        Ok(nats)
+    }
+
+    fn publish(&mut self, subject: &str, payload: &[u8]) -> AnyhowResult<()> {
+        write!(self.writer, "PUB {} {}\r\n", subject, payload.len())?;
+        self.writer.write_all(payload)?;
+        self.writer.write_all(b"\r\n")?;
+
+        Ok(())
+    }
+
+    /// Round-trips a PING, surfacing any `-ERR` the server sent in between
+    fn flush(&mut self) -> AnyhowResult<()> {
+        self.writer.write_all(b"PING\r\n")?;
+
+        loop {
+            let line = self.read_line()?;
+            debug!("nats: {}", line);
+
+            match line.as_str() {
+                "PONG" => return Ok(()),
+                "PING" => self.writer.write_all(b"PONG\r\n")?,
+                line if line.starts_with("-ERR") => bail!("nats error: {}", line),
+                _ => {}
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> AnyhowResult<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("nats closed the connection");
+        }
+
+        Ok(line.trim_end().to_string())
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use log::info;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::env::interpolate;
+use crate::types::TorConfig;
+
+const CONTROL_TIMEOUT_SECS: u64 = 10;
+
+/// A local Tor daemon, requests go through its SOCKS port and new circuits
+/// are asked for on its control port
+pub struct Tor {
+    pub proxy: String,
+    control: String,
+    /// `password` with environment variables resolved
+    password: Option<String>,
+    newnym_every: Option<u32>,
+    /// requests sent since the last new identity
+    requests: Mutex<u32>,
+}
+
+impl Tor {
+    pub fn new(config: &TorConfig) -> AnyhowResult<Self> {
+        Ok(Tor {
+            // `socks5h` so hosts are resolved by Tor, not leaked to local DNS
+            proxy: format!("socks5h://{}", config.socks),
+            control: config.control.clone(),
+            password: config.password.as_deref().map(interpolate).transpose()?,
+            newnym_every: config.newnym_every,
+            requests: Mutex::new(0),
+        })
+    }
+
+    /// Counts a request, true once `newnymEvery` requests went through the
+    /// current identity
+    pub fn due(&self) -> bool {
+        let Some(every) = self.newnym_every else {
+            return false;
+        };
+
+        let mut requests = self.requests.lock().unwrap();
+        *requests += 1;
+        if *requests < every {
+            return false;
+        }
+        *requests = 0;
+        true
+    }
+
+    /// Signals `NEWNYM` so new connections use new circuits
+    pub fn new_identity(&self) -> AnyhowResult<()> {
+        let stream = TcpStream::connect(&self.control)
+            .with_context(|| format!("can't connect to Tor control port `{}`", self.control))?;
+        stream.set_read_timeout(Some(Duration::from_secs(CONTROL_TIMEOUT_SECS)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let authenticate = match &self.password {
+            Some(password) => format!("AUTHENTICATE \"{}\"", escape(password)),
+            None => "AUTHENTICATE".to_string(),
+        };
+        for command in [authenticate.as_str(), "SIGNAL NEWNYM"] {
+            write!(writer, "{}\r\n", command)?;
+
+            let mut reply = String::new();
+            reader.read_line(&mut reply)?;
+            if !reply.starts_with("250") {
+                let command = command.split(' ').next().unwrap_or_default();
+                bail!("Tor refused `{}`: {}", command, reply.trim());
+            }
+        }
+        write!(writer, "QUIT\r\n")?;
+
+        *self.requests.lock().unwrap() = 0;
+        info!("asked Tor for a new identity");
+        Ok(())
+    }
+}
+
+fn escape(password: &str) -> String {
+    password.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_config;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn tor_config_test() {
+        let tor = |tor: &str| {
+            parse_config(&format!(
+                "config: {{ url: 'https://example.com', tor: {} }}",
+                tor
+            ))
+            .map(|config| config.config.tor())
+        };
+
+        assert!(tor("false").unwrap().is_none());
+        assert_eq!(tor("true").unwrap().unwrap().socks, "127.0.0.1:9050");
+        let custom = tor("{ socks: '127.0.0.1:9150', newnymEvery: 2 }")
+            .unwrap()
+            .unwrap();
+        assert_eq!(custom.socks, "127.0.0.1:9150");
+        assert_eq!(custom.control, "127.0.0.1:9051");
+        assert!(tor("{ newnymEvery: 0 }").is_err());
+        assert!(parse_config(
+            "config: { url: 'https://example.com', tor: true, proxies: ['http://proxy:8080'] }"
+        )
+        .is_err());
+
+        let tor = Tor::new(&custom).unwrap();
+        assert_eq!(tor.proxy, "socks5h://127.0.0.1:9150");
+        assert_eq!([tor.due(), tor.due(), tor.due()], [false, true, false]);
+    }
+
+    #[test]
+    fn new_identity_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let control = listener.local_addr().unwrap().to_string();
+        let daemon = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = vec![];
+            for _ in 0..2 {
+                let mut command = String::new();
+                reader.read_line(&mut command).unwrap();
+                commands.push(command.trim_end().to_string());
+                write!(writer, "250 OK\r\n").unwrap();
+            }
+            commands
+        });
+
+        let tor = Tor::new(&TorConfig {
+            control,
+            password: Some(r#"pa"ss"#.to_string()),
+            newnym_every: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(tor.due());
+        tor.new_identity().unwrap();
+
+        assert_eq!(
+            daemon.join().unwrap(),
+            [r#"AUTHENTICATE "pa\"ss""#, "SIGNAL NEWNYM"]
+        );
+    }
+}
@@ -0,0 +1,549 @@
+use phonenumber::Mode;
+use regex::Regex;
+use scraper::Html;
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use slug::slugify;
+use std::env;
+use unicode_normalization::UnicodeNormalization;
+use url::{ParseError, Url};
+use validator::validate_email;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::types::{
+    DataConfig, HashAlgorithm, ItemConfig, Redact, ReturnedData, ReturnedDataItem, UnicodeForm,
+};
+
+/// Environment variable whose value salts `redact: hash`
+pub const REDACT_SALT: &str = "KARKINOS_REDACT_SALT";
+
+/// The output value of an extracted field
+pub fn transform(config: &ItemConfig, value: String) -> ReturnedDataItem {
+    transform_traced(config, value, &mut Trace::default())
+}
+
+/// What `transform` did to a value, for `--explain`
+#[derive(Default, Debug)]
+pub struct Trace {
+    /// the step that turned a non-empty value empty or null
+    pub emptied_by: Option<&'static str>,
+    /// whether `mapDefault` replaced the value
+    pub default: bool,
+    empty: bool,
+}
+
+impl Trace {
+    fn step(&mut self, name: &'static str, value: &str) {
+        let empty = value.trim().is_empty();
+        if empty && !self.empty {
+            self.emptied_by.get_or_insert(name);
+        }
+        self.empty = empty;
+    }
+
+    fn nulled(&mut self, name: &'static str) -> ReturnedDataItem {
+        if !self.empty {
+            self.emptied_by.get_or_insert(name);
+        }
+        ReturnedDataItem::JsonItem(Value::Null)
+    }
+}
+
+/// `transform`, recording in `trace` which step emptied the value
+pub fn transform_traced(config: &ItemConfig, value: String, trace: &mut Trace) -> ReturnedDataItem {
+    trace.empty = value.trim().is_empty();
+    let value = if config.trim {
+        value.trim().to_string()
+    } else {
+        value
+    };
+    let value = match config.normalize_unicode {
+        Some(UnicodeForm::Nfc) => value.nfc().collect(),
+        Some(UnicodeForm::Nfd) => value.nfd().collect(),
+        Some(UnicodeForm::Nfkc) => value.nfkc().collect(),
+        Some(UnicodeForm::Nfkd) => value.nfkd().collect(),
+        None => value,
+    };
+    trace.step("normalizeUnicode", &value);
+    let value = match &config.regex {
+        Some(_) => match config
+            .get_regex()
+            .and_then(|regex| first_match(regex, &value))
+        {
+            Some(matched) => matched,
+            None => return trace.nulled("regex"),
+        },
+        None => value,
+    };
+    trace.step("regex", &value);
+    let value = match config.slice {
+        Some((start, end)) => slice(&value, start, end),
+        None => value,
+    };
+    trace.step("slice", &value);
+    let value = match &config.remove_prefix {
+        Some(prefix) => match value.trim_start().strip_prefix(prefix.as_str()) {
+            Some(rest) => rest.trim_start().to_string(),
+            None => value,
+        },
+        None => value,
+    };
+    trace.step("removePrefix", &value);
+    let value = match &config.remove_suffix {
+        Some(suffix) => match value.trim_end().strip_suffix(suffix.as_str()) {
+            Some(rest) => rest.trim_end().to_string(),
+            None => value,
+        },
+        None => value,
+    };
+    trace.step("removeSuffix", &value);
+    let value = match config.truncate {
+        Some(length) => value.chars().take(length).collect(),
+        None => value,
+    };
+    trace.step("truncate", &value);
+    let value = match &config.url_part {
+        Some(part) => match url_part(&value, part) {
+            Some(part) => part,
+            None => return trace.nulled("urlPart"),
+        },
+        None => value,
+    };
+    trace.step("urlPart", &value);
+    let value = if config.slugify {
+        slugify(value)
+    } else {
+        value
+    };
+    trace.step("slugify", &value);
+    let value = if config.to_email {
+        match to_email(&value) {
+            Some(email) => email,
+            None => return trace.nulled("toEmail"),
+        }
+    } else {
+        value
+    };
+    trace.step("toEmail", &value);
+    let value = match &config.to_phone {
+        Some(country) => match to_phone(&value, country) {
+            Some(phone) => phone,
+            None => return trace.nulled("toPhone"),
+        },
+        None => value,
+    };
+    trace.step("toPhone", &value);
+    let mapped = if config.map.is_empty() {
+        None
+    } else {
+        let mapped = config.map.get(value.trim());
+        trace.default = mapped.is_none() && config.map_default.is_some();
+        mapped.or(config.map_default.as_ref())
+    };
+    let value = match mapped {
+        Some(Value::String(mapped)) => mapped.clone(),
+        Some(mapped) => return ReturnedDataItem::JsonItem(mapped.clone()),
+        None => value,
+    };
+    trace.step("map", &value);
+    let value = match config.redact {
+        Some(redact) => self::redact(redact, &value),
+        None => value,
+    };
+    trace.step("redact", &value);
+
+    ReturnedDataItem::StringItem(value)
+}
+
+/// The first match of `regex`, or of its first group
+fn first_match(regex: &Regex, value: &str) -> Option<String> {
+    let captures = regex.captures(value)?;
+    captures
+        .get(1)
+        .or_else(|| captures.get(0))
+        .map(|matched| matched.as_str().to_string())
+}
+
+/// A part of the url, relative ones are resolved against a placeholder base
+/// whose scheme and host aren't theirs
+fn url_part(value: &str, part: &str) -> Option<String> {
+    let value = value.trim();
+    let (url, relative) = match Url::parse(value) {
+        Ok(url) => (url, false),
+        Err(ParseError::RelativeUrlWithoutBase) => (
+            Url::parse("http://relative.invalid/")
+                .ok()?
+                .join(value)
+                .ok()?,
+            true,
+        ),
+        Err(_) => return None,
+    };
+
+    let part = match part {
+        "scheme" | "host" if relative => return None,
+        "scheme" => url.scheme(),
+        "host" => url.host_str()?,
+        "path" => url.path(),
+        "query" => url.query()?,
+        "fragment" => url.fragment()?,
+        part => {
+            let name = part.strip_prefix("query.")?;
+            return url
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned());
+        }
+    };
+
+    Some(part.to_string())
+}
+
+/// The lowercased address of an email or a `mailto:` link
+fn to_email(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value.strip_prefix("mailto:").unwrap_or(value);
+    let email = value.split('?').next()?.to_lowercase();
+
+    validate_email(&email).then_some(email)
+}
+
+/// The E.164 form of a valid phone number, of `country` without a `+`
+fn to_phone(value: &str, country: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value.strip_prefix("tel:").unwrap_or(value);
+    let number = phonenumber::parse(country.parse().ok(), value).ok()?;
+
+    phonenumber::is_valid(&number).then(|| number.format().mode(Mode::E164).to_string())
+}
+
+/// Characters from `start` to `end`, negative indices count from the end
+fn slice(value: &str, start: isize, end: Option<isize>) -> String {
+    let length = value.chars().count() as isize;
+    let index = |index: isize| {
+        let index = if index < 0 { length + index } else { index };
+        index.clamp(0, length) as usize
+    };
+    let (start, end) = (index(start), end.map_or(length as usize, index));
+
+    value
+        .chars()
+        .skip(start)
+        .take(end.saturating_sub(start))
+        .collect()
+}
+
+/// Fields added next to the `name` field, derived from its `value`
+pub fn companions(
+    name: &str,
+    config: &ItemConfig,
+    value: &ReturnedDataItem,
+) -> Vec<(String, ReturnedDataItem)> {
+    let ReturnedDataItem::StringItem(value) = value else {
+        return vec![];
+    };
+    let mut companions = vec![];
+    let text = text(value);
+
+    if config.detect_language {
+        companions.push((
+            format!("{}_language", name),
+            ReturnedDataItem::StringItem(detect_language(&text).unwrap_or_default().to_string()),
+        ));
+    }
+    if let Some(algorithm) = config.hash {
+        companions.push((
+            format!("{}_hash", name),
+            ReturnedDataItem::StringItem(hash(algorithm, value.as_bytes())),
+        ));
+    }
+    if config.length {
+        companions.push((
+            format!("{}_length", name),
+            ReturnedDataItem::JsonItem(text.chars().count().into()),
+        ));
+    }
+    if config.word_count {
+        companions.push((
+            format!("{}_word_count", name),
+            ReturnedDataItem::JsonItem(text.split_whitespace().count().into()),
+        ));
+    }
+
+    companions
+}
+
+/// The SHA-1 of the JSON list of the `fields` values
+pub fn record_id(fields: &[String], data: &ReturnedData) -> ReturnedDataItem {
+    let values = fields
+        .iter()
+        .map(|field| data.get(field))
+        .collect::<Vec<_>>();
+    let values = serde_json::to_vec(&values).unwrap_or_default();
+
+    ReturnedDataItem::StringItem(hash(HashAlgorithm::Sha1, &values))
+}
+
+fn hash(algorithm: HashAlgorithm, content: &[u8]) -> String {
+    match algorithm {
+        HashAlgorithm::Sha1 => hex::encode(Sha1::digest(content)),
+        HashAlgorithm::Xxhash => format!("{:016x}", xxh3_64(content)),
+    }
+}
+
+/// False when a field's detected language isn't one of its `languages`
+pub fn keep(config: &DataConfig, data: &ReturnedData) -> bool {
+    config
+        .iter()
+        .filter(|(_, config)| !config.languages.is_empty())
+        .all(|(name, config)| {
+            let language = match data.get(name) {
+                Some(ReturnedDataItem::StringItem(value)) => detect_language(&text(value)),
+                _ => None,
+            };
+            language.is_some_and(|language| config.languages.iter().any(|code| code == language))
+        })
+}
+
+/// The value with its markup left out, trimmed
+fn text(value: &str) -> String {
+    let text = Html::parse_fragment(value)
+        .root_element()
+        .text()
+        .collect::<String>();
+
+    text.trim().to_string()
+}
+
+/// ISO 639-3 code of the language of the text
+fn detect_language(text: &str) -> Option<&'static str> {
+    whatlang::detect(text).map(|info| info.lang().code())
+}
+
+/// Empty values stay empty, so missing fields can still be told apart
+fn redact(redact: Redact, value: &str) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+
+    match redact {
+        Redact::Hash => {
+            let salt = env::var(REDACT_SALT).unwrap_or_default();
+            hex::encode(Sha256::digest(format!("{}{}", salt, value)))
+        }
+        Redact::Mask => match value.split_once('@') {
+            Some((user, domain)) => {
+                let first = user.chars().next().map(String::from).unwrap_or_default();
+                format!("{}***@{}", first, domain)
+            }
+            None => mask(value),
+        },
+    }
+}
+
+/// `*` for every letter and digit but the last 4, separators are kept
+fn mask(value: &str) -> String {
+    let total = value.chars().filter(|c| c.is_alphanumeric()).count();
+    let mut seen = 0;
+
+    value
+        .chars()
+        .map(|c| {
+            if !c.is_alphanumeric() {
+                return c;
+            }
+            seen += 1;
+            if seen + 4 > total {
+                c
+            } else {
+                '*'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_language_test() {
+        assert_eq!(
+            detect_language("The quick brown fox jumps over the lazy dog."),
+            Some("eng")
+        );
+        assert_eq!(
+            detect_language("Der schnelle braune Fuchs springt über den faulen Hund."),
+            Some("deu")
+        );
+        assert_eq!(detect_language(""), None);
+    }
+
+    #[test]
+    fn transform_test() {
+        let value = |yaml: &str, value: &str| {
+            let config = serde_yaml::from_str::<ItemConfig>(yaml).unwrap();
+            transform(&config, value.to_string())
+        };
+        let string = |value: &str| ReturnedDataItem::StringItem(value.to_string());
+
+        assert_eq!(
+            value("{ selector: p, removePrefix: 'Price:' }", " Price: 12 €"),
+            string("12 €")
+        );
+        assert_eq!(
+            value(
+                "{ selector: p, removeSuffix: Read more » }",
+                "A lamp. Read more »\n"
+            ),
+            string("A lamp.")
+        );
+        assert_eq!(
+            value("{ selector: p, truncate: 4 }", "Lämpchen"),
+            string("Lämp")
+        );
+        assert_eq!(
+            value("{ selector: p, slice: [1, -1] }", "[SKU]"),
+            string("SKU")
+        );
+        assert_eq!(
+            value("{ selector: p, slice: [-3, null] }", "ID 042"),
+            string("042")
+        );
+        assert_eq!(value("{ selector: p, slice: [4, 2] }", "value"), string(""));
+        assert_eq!(
+            value("{ selector: p, removePrefix: $ }", " $5"),
+            string("5")
+        );
+        assert_eq!(
+            value("{ selector: p, truncate: 4 }", "\n  Lamp shade"),
+            string("Lamp")
+        );
+        assert_eq!(
+            value("{ selector: p, slice: [0, 2] }", "  42 "),
+            string("42")
+        );
+        assert_eq!(
+            value("{ selector: p, trim: false, truncate: 4 }", "  Lamp"),
+            string("  La")
+        );
+        assert_eq!(
+            value("{ selector: p, normalizeUnicode: nfkc }", "ﬁne Ｌａｍｐ"),
+            string("fine Lamp")
+        );
+        assert_eq!(
+            value("{ selector: p, slugify: true }", " Lampe « Émile » 60W "),
+            string("lampe-emile-60w")
+        );
+
+        let availability = "{ selector: p, map: { In stock: true, Sold out: false } }";
+        assert_eq!(
+            value(availability, " In stock "),
+            ReturnedDataItem::JsonItem(Value::Bool(true))
+        );
+        assert_eq!(value(availability, "Preorder"), string("Preorder"));
+        assert_eq!(
+            value(
+                "{ selector: p, map: { In stock: available }, mapDefault: unavailable }",
+                "Preorder"
+            ),
+            string("unavailable")
+        );
+    }
+
+    #[test]
+    fn url_part_test() {
+        let url = "https://shop.example/p/lamp?id=42&ref=home#reviews";
+
+        assert_eq!(url_part(url, "host"), Some("shop.example".to_string()));
+        assert_eq!(url_part(url, "path"), Some("/p/lamp".to_string()));
+        assert_eq!(url_part(url, "query.id"), Some("42".to_string()));
+        assert_eq!(url_part(url, "fragment"), Some("reviews".to_string()));
+        assert_eq!(url_part(url, "query.page"), None);
+        assert_eq!(url_part("/p/desk?id=7", "query.id"), Some("7".to_string()));
+        assert_eq!(url_part("/p/desk?id=7", "host"), None);
+    }
+
+    #[test]
+    fn contact_test() {
+        assert_eq!(
+            to_email(" mailto:Jane.Doe@Example.com?subject=Hi"),
+            Some("jane.doe@example.com".to_string())
+        );
+        assert_eq!(to_email("contact us"), None);
+        assert_eq!(
+            to_phone("(415) 555-2671", "US"),
+            Some("+14155552671".to_string())
+        );
+        assert_eq!(
+            to_phone("tel:+44 20 7946 0958", "US"),
+            Some("+442079460958".to_string())
+        );
+        assert_eq!(to_phone("call us", "US"), None);
+    }
+
+    #[test]
+    fn companions_test() {
+        let config =
+            serde_yaml::from_str::<ItemConfig>("{ selector: p, length: true, wordCount: true }")
+                .unwrap();
+        let value = ReturnedDataItem::StringItem(" <b>Read</b> more  here ".to_string());
+
+        assert_eq!(
+            companions("content", &config, &value),
+            [
+                (
+                    "content_length".to_string(),
+                    ReturnedDataItem::JsonItem(15.into())
+                ),
+                (
+                    "content_word_count".to_string(),
+                    ReturnedDataItem::JsonItem(3.into())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn record_id_test() {
+        let record = |sku: &str, title: &str| {
+            ReturnedData::from([
+                (
+                    "sku".to_string(),
+                    ReturnedDataItem::StringItem(sku.to_string()),
+                ),
+                (
+                    "title".to_string(),
+                    ReturnedDataItem::StringItem(title.to_string()),
+                ),
+            ])
+        };
+        let fields = ["sku".to_string()];
+
+        assert_eq!(
+            record_id(&fields, &record("L-1", "Lamp")),
+            record_id(&fields, &record("L-1", "Red lamp"))
+        );
+        assert_ne!(
+            record_id(&fields, &record("L-1", "Lamp")),
+            record_id(&fields, &record("L-2", "Lamp"))
+        );
+        assert_eq!(hash(HashAlgorithm::Xxhash, b"lamp").len(), 16);
+    }
+
+    #[test]
+    fn redact_test() {
+        assert_eq!(
+            redact(Redact::Mask, "jane.doe@example.com"),
+            "j***@example.com"
+        );
+        assert_eq!(redact(Redact::Mask, "+1 555-123-4567"), "+* ***-***-4567");
+        assert_eq!(redact(Redact::Mask, ""), "");
+        assert_eq!(
+            redact(Redact::Hash, "jane.doe@example.com"),
+            redact(Redact::Hash, "jane.doe@example.com")
+        );
+        assert_eq!(redact(Redact::Hash, "a").len(), 64);
+    }
+}
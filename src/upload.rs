@@ -0,0 +1,107 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use chrono::Utc;
+use log::info;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use std::env;
+
+use crate::sigv4::{encode, Credentials, Signer};
+
+/// True for destinations handled by `upload` rather than written to disk
+pub fn is_remote(destination: &str) -> bool {
+    destination.starts_with("s3://") || destination.starts_with("gs://")
+}
+
+/// Uploads `body` to an `s3://bucket/key` or `gs://bucket/object` destination
+pub fn upload(destination: &str, body: Vec<u8>, content_type: &str) -> AnyhowResult<()> {
+    let (scheme, rest) = destination.split_once("://").unwrap_or_default();
+    let Some((bucket, key)) = rest.split_once('/').filter(|(_, key)| !key.is_empty()) else {
+        bail!(
+            "invalid upload destination `{}`, expected `{}://bucket/key`",
+            destination,
+            scheme
+        );
+    };
+
+    info!("uploading output to `{}`", destination);
+    match scheme {
+        "s3" => upload_s3(bucket, key, body, content_type),
+        "gs" => upload_gcs(bucket, key, body, content_type),
+        _ => bail!("unsupported upload destination `{}`", destination),
+    }
+}
+
+/// `AWS_ENDPOINT_URL` switches to path-style requests for S3 compatible stores
+fn upload_s3(bucket: &str, key: &str, body: Vec<u8>, content_type: &str) -> AnyhowResult<()> {
+    let credentials = Credentials::from_env()?;
+    let region = env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|_| "us-east-1".to_string());
+
+    let key = key.split('/').map(encode).collect::<Vec<_>>().join("/");
+    let url = match env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), bucket, key),
+        Err(_) => format!("https://{}.s3.{}.amazonaws.com/{}", bucket, region, key),
+    };
+    let url = Url::parse(&url).with_context(|| format!("invalid s3 url `{}`", url))?;
+
+    let signer = Signer {
+        credentials: &credentials,
+        region: &region,
+        service: "s3",
+    };
+    let headers = signer.sign(
+        "PUT",
+        &url,
+        &[("content-type".to_string(), content_type.to_string())],
+        &body,
+        Utc::now(),
+    );
+
+    let mut request = Client::new().put(url).body(body);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+
+    let response = request.send().context("can't upload to s3")?;
+    if !response.status().is_success() {
+        bail!(
+            "s3 upload failed with {}: {}",
+            response.status(),
+            response.text().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+/// Uses the access token from `GOOGLE_OAUTH_ACCESS_TOKEN`
+fn upload_gcs(bucket: &str, object: &str, body: Vec<u8>, content_type: &str) -> AnyhowResult<()> {
+    let token =
+        env::var("GOOGLE_OAUTH_ACCESS_TOKEN").context("GOOGLE_OAUTH_ACCESS_TOKEN is not set")?;
+
+    let mut url = Url::parse(&format!(
+        "https://storage.googleapis.com/upload/storage/v1/b/{}/o",
+        encode(bucket)
+    ))?;
+    url.query_pairs_mut()
+        .append_pair("uploadType", "media")
+        .append_pair("name", object);
+
+    let response = Client::new()
+        .post(url)
+        .bearer_auth(token)
+        .header("content-type", content_type)
+        .body(body)
+        .send()
+        .context("can't upload to gcs")?;
+    if !response.status().is_success() {
+        bail!(
+            "gcs upload failed with {}: {}",
+            response.status(),
+            response.text().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result as AnyhowResult};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::VisitedConfig;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS visited (
+    url TEXT PRIMARY KEY,
+    fetched_at INTEGER NOT NULL
+)";
+
+/// Urls scraped by earlier runs, kept in SQLite so interrupted or repeated
+/// runs skip what they already have
+pub struct Visited {
+    connection: Connection,
+    revisit_after: Option<u64>,
+}
+
+impl Visited {
+    pub fn open(config: &VisitedConfig) -> AnyhowResult<Self> {
+        let connection = Connection::open(&config.path)
+            .with_context(|| format!("can't open visited urls `{}`", config.path))?;
+        connection
+            .execute(CREATE_TABLE, [])
+            .with_context(|| format!("can't create visited table in `{}`", config.path))?;
+
+        Ok(Visited {
+            connection,
+            revisit_after: config.revisit_after,
+        })
+    }
+
+    /// Scraped within `revisitAfter` seconds, or ever without it
+    pub fn is_fresh(&self, url: &str) -> AnyhowResult<bool> {
+        let fetched_at: Option<u64> = self
+            .connection
+            .query_row(
+                "SELECT fetched_at FROM visited WHERE url = ?1",
+                params![url],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(match (fetched_at, self.revisit_after) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(fetched_at), Some(revisit_after)) => now() < fetched_at + revisit_after,
+        })
+    }
+
+    /// Records `url` as scraped now, written at once
+    pub fn mark(&self, url: &str) -> AnyhowResult<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO visited (url, fetched_at) VALUES (?1, ?2)",
+                params![url, now()],
+            )
+            .context("can't write visited urls")?;
+
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    #[test]
+    fn resume_test() {
+        let path = env::temp_dir().join(format!("karkinos-visited-test-{}.db", process::id()));
+        let config = VisitedConfig {
+            path: path.to_string_lossy().into_owned(),
+            revisit_after: None,
+        };
+
+        // a run interrupted after scraping its first two urls
+        let run = Visited::open(&config).unwrap();
+        run.mark("https://example.com/1").unwrap();
+        run.mark("https://example.com/2").unwrap();
+        drop(run);
+
+        let next_run = Visited::open(&config).unwrap();
+        assert!(next_run.is_fresh("https://example.com/1").unwrap());
+        assert!(next_run.is_fresh("https://example.com/2").unwrap());
+        assert!(!next_run.is_fresh("https://example.com/3").unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{bail, Context, Result as AnyhowResult};
+use chrono::{SecondsFormat, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::types::Page;
+
+/// headers that describe the original transfer, not the body we archive
+const SKIPPED_HEADERS: [&str; 3] = ["content-encoding", "content-length", "transfer-encoding"];
+
+/// Appends fetched responses to a WARC file, gzipped per record for `.gz` paths
+pub struct WarcWriter {
+    file: File,
+    compress: bool,
+}
+
+impl WarcWriter {
+    pub fn create(path: &Path) -> AnyhowResult<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("can't create warc file `{}`", path.display()))?;
+        let mut writer = WarcWriter {
+            file,
+            compress: path.extension().is_some_and(|extension| extension == "gz"),
+        };
+
+        let info = format!(
+            "software: karkinos/{}\r\nformat: WARC File Format 1.1\r\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        writer.write_record("warcinfo", None, "application/warc-fields", info.as_bytes())?;
+
+        Ok(writer)
+    }
+
+    pub fn write_response(&mut self, url: &str, page: &Page) -> AnyhowResult<()> {
+        let reason = StatusCode::from_u16(page.status)
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or_default();
+        let mut http = format!("HTTP/1.1 {} {}\r\n", page.status, reason);
+        for (name, value) in &page.headers {
+            if !SKIPPED_HEADERS.contains(&name.to_lowercase().as_str()) {
+                http.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        http.push_str(&format!("Content-Length: {}\r\n\r\n", page.html.len()));
+        http.push_str(&page.html);
+
+        // archived under the requested url so replays can look it up again
+        self.write_record(
+            "response",
+            Some(url),
+            "application/http;msgtype=response",
+            http.as_bytes(),
+        )
+    }
+
+    fn write_record(
+        &mut self,
+        kind: &str,
+        target: Option<&str>,
+        content_type: &str,
+        block: &[u8],
+    ) -> AnyhowResult<()> {
+        let mut record = format!(
+            "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: <urn:uuid:{}>\r\nWARC-Date: {}\r\n",
+            kind,
+            Uuid::new_v4(),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        if let Some(target) = target {
+            record.push_str(&format!("WARC-Target-URI: {}\r\n", target));
+        }
+        record.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            content_type,
+            block.len()
+        ));
+
+        let mut bytes = record.into_bytes();
+        bytes.extend_from_slice(block);
+        bytes.extend_from_slice(b"\r\n\r\n");
+
+        if self.compress {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(&bytes)?;
+            bytes = encoder.finish()?;
+        }
+
+        self.file
+            .write_all(&bytes)
+            .context("can't write warc record")
+    }
+}
+
+/// Response records of a WARC file by target url
+pub fn read_archive(path: &Path) -> AnyhowResult<HashMap<String, Page>> {
+    let file =
+        File::open(path).with_context(|| format!("can't open warc file `{}`", path.display()))?;
+    let reader: Box<dyn Read> = match path.extension().is_some_and(|extension| extension == "gz") {
+        true => Box::new(MultiGzDecoder::new(file)),
+        false => Box::new(file),
+    };
+    let mut reader = BufReader::new(reader);
+
+    let mut pages = HashMap::new();
+    while let Some((headers, block)) = read_record(&mut reader)? {
+        let is_response = headers.get("warc-type").map(String::as_str) == Some("response");
+
+        match headers.get("warc-target-uri") {
+            Some(target) if is_response => {
+                let page = parse_http_response(target, &block)?;
+                pages.insert(target.clone(), page);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(pages)
+}
+
+/// WARC headers (lowercased names) and content block
+type Record = (HashMap<String, String>, Vec<u8>);
+
+/// The next record, `None` at the end of the file
+fn read_record(reader: &mut impl BufRead) -> AnyhowResult<Option<Record>> {
+    let mut line = String::new();
+
+    // skip the blank lines separating records
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+    if !line.starts_with("WARC/") {
+        bail!("invalid warc record: {}", line.trim());
+    }
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .context("warc record without content-length")?;
+    let mut block = vec![0; length];
+    reader.read_exact(&mut block)?;
+
+    Ok(Some((headers, block)))
+}
+
+fn parse_http_response(url: &str, block: &[u8]) -> AnyhowResult<Page> {
+    let split = block
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .with_context(|| format!("invalid http response for `{}`", url))?;
+    let head = String::from_utf8_lossy(&block[..split]);
+    let body = &block[split + 4..];
+
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .with_context(|| format!("invalid http status line for `{}`", url))?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect::<Vec<_>>();
+    let location = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+        .map(|(_, value)| value.clone())
+        .filter(|_| (300..400).contains(&status));
+
+    Ok(Page {
+        html: String::from_utf8_lossy(body).into_owned(),
+        final_url: url.to_string(),
+        status,
+        location,
+        headers,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::Page;
+    use crate::warc::{read_archive, WarcWriter};
+    use std::env;
+
+    #[test]
+    fn warc_roundtrip_test() {
+        let path = env::temp_dir().join(format!("karkinos-test-{}.warc.gz", std::process::id()));
+        let page = Page {
+            html: "<h1>archived</h1>".to_string(),
+            final_url: "https://example.com/".to_string(),
+            status: 200,
+            location: None,
+            headers: vec![("content-type".to_string(), "text/html".to_string())],
+            ..Default::default()
+        };
+
+        let mut writer = WarcWriter::create(&path).unwrap();
+        writer
+            .write_response("https://example.com/", &page)
+            .unwrap();
+        drop(writer);
+
+        let pages = read_archive(&path).unwrap();
+        let archived = pages.get("https://example.com/").unwrap();
+        assert_eq!(archived.html, page.html);
+        assert_eq!(archived.status, 200);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
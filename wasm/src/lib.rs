@@ -0,0 +1,17 @@
+use karkinos::populate_values;
+use karkinos::types::{validate_data_selectors, DataConfig, Page};
+use wasm_bindgen::prelude::*;
+
+/// Extracts the `data` fields (as JSON) from HTML the caller already has,
+/// returning the record as JSON
+#[wasm_bindgen(js_name = scrapeHtml)]
+pub fn scrape_html(html: String, data: &str) -> Result<String, JsError> {
+    let data = serde_json::from_str::<DataConfig>(data)?;
+    validate_data_selectors(&data)?;
+    let page = Page {
+        html,
+        ..Default::default()
+    };
+
+    Ok(serde_json::to_string(&populate_values(&page, &data))?)
+}